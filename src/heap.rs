@@ -72,15 +72,46 @@
 //! We suggest *never* implementing `Drop` for a heap type. If you must,
 //! avoid reading pointer fields while dropping, and avoid calling into
 //! arbitrary code.
+//!
+//! If all you need is a cleanup hook -- closing a file handle, say -- prefer
+//! implementing `Finalize` instead. Its `finalize()` runs during
+//! `gc_cycle`, after marking but before any memory is reclaimed, and is
+//! handed a value rebuilt by `from_heap()` rather than a direct reference
+//! into the heap, so none of the above hazards apply. See the
+//! "Finalization" section of the `pages` module docs.
+//!
+//! ### Ephemerons
+//!
+//! `GcEphemeronRef<K, V>` (see below) is a weak key paired with a value
+//! that's retained only while the key is independently reachable -- the
+//! building block for a weak hash map. Unlike an ordinary field, the value
+//! edge must never be traced during ordinary marking, or the value would
+//! keep itself alive regardless of the key; instead, `marking::mark` is
+//! assumed to run a fixpoint loop after ordinary transitive marking settles:
+//! repeatedly call `GcHeap::ephemeron_targets` for the current
+//! `(key, value)` pairs, mark the value (and push it onto the mark stack)
+//! of every ephemeron whose key is already marked, and redo the whole
+//! "drain mark stack, rescan ephemerons" cycle until a full pass marks no
+//! new value. `GcHeap::invalidate_dead_ephemerons` then runs once that
+//! settles, clearing the value slot of every ephemeron whose key didn't
+//! survive, so sweep can reclaim it like anything else unreachable. The
+//! fixpoint loop itself lives in `marking::mark`, which isn't part of this
+//! snapshot.
 
 use gc_ref::{GcFrozenRef, GcRef};
-use marking::{MarkingTracer, mark};
-use pages::{self, PageSet, PageSetRef, TypeId, TypedPage, heap_type_id};
+use marking::{MarkingTracer, fixup_forwarded, mark};
+use pages::{self, PageSet, PageSetRef, TypeId, TypedPage, TypeStats, heap_type_id};
 use ptr::{Pointer, UntypedPointer};
 use signposts;
 use std::collections::HashMap;
+#[cfg(feature = "persistent")]
+use std::fs::File;
+#[cfg(feature = "persistent")]
+use std::io;
 use std::marker::PhantomData;
 use std::mem;
+#[cfg(feature = "provenance")]
+use std::panic::Location;
 use std::ptr;
 use std::sync::{Arc, Mutex, Weak};
 use traits::IntoHeapAllocation;
@@ -103,6 +134,231 @@ pub struct GcHeap {
     /// `GcFrozenRef` uses it to prevent you from freezing a reference into
     /// one heap, then thawing it in a different heap, you monster.
     dropped_frozen_ptrs: Arc<Mutex<Vec<UntypedPointer>>>,
+
+    /// If true, `gc_cycle` runs a compacting pass (see `pages::PageSet::compact`)
+    /// between marking and sweeping, relocating live, unpinned objects out of
+    /// full pages so they can be freed. Off by default: compaction means
+    /// more work per cycle in exchange for lower steady-state fragmentation.
+    compacting: bool,
+
+    /// Allocation site of each currently-live allocation, keyed by its
+    /// address. Only present with `--features provenance`; see
+    /// `GcHeapSession::provenance_of`. Entries for freed addresses are not
+    /// pruned eagerly — they're simply overwritten the next time that
+    /// address is reused by `try_alloc` — so a leak hunt should cross-check
+    /// against `heap_stats`/liveness before trusting a stale-looking one.
+    #[cfg(feature = "provenance")]
+    provenance: HashMap<usize, &'static Location<'static>>,
+
+    /// Slots backing every live `GcWeakRef`, so `gc_cycle` can invalidate
+    /// the ones whose target didn't survive marking. Holds `Weak` handles,
+    /// not the slots themselves, so a dropped `GcWeakRef` doesn't need to
+    /// eagerly deregister anything -- its entry is simply pruned the next
+    /// time this is scanned.
+    weak_refs: Mutex<Vec<Weak<Mutex<Option<UntypedPointer>>>>>,
+
+    /// Slots backing every live `Root<T>`, scanned by `clear_mark_bits` and
+    /// added to the root set alongside pinned `GcRef`s (see the "root set"
+    /// discussion in the `pages` module docs). Unlike a pin count, a root
+    /// here doesn't make its page ineligible for `compact`; it's just an
+    /// extra edge into the live graph, like a pointer on the native stack
+    /// would be. Holds `Weak` handles, not the pointers themselves, so a
+    /// dropped `Root` doesn't need to eagerly deregister anything -- its
+    /// entry is simply pruned the next time this is scanned, the same way
+    /// `weak_refs` and `ephemerons` are.
+    roots: Mutex<Vec<Weak<Mutex<UntypedPointer>>>>,
+
+    /// Escalation policy for the automatic collection `try_alloc` runs when
+    /// a page-limited type runs out of room (see `GcHeapSession::
+    /// set_generational_gc`). `None` (the default) means every automatic
+    /// collection is a full `gc_cycle`. `Some(n)` means up to `n`
+    /// consecutive automatic collections run `minor_collect` instead,
+    /// before `minor_collections_since_major` rolls back over and the next
+    /// one is a full collection again.
+    generational_gc_threshold: Option<usize>,
+
+    /// How many automatic minor collections have run since the last
+    /// automatic full collection; compared against
+    /// `generational_gc_threshold` by `collect_for_allocation`.
+    minor_collections_since_major: usize,
+
+    /// Slots backing every live `GcEphemeronRef`, each holding a `(key,
+    /// value)` pointer pair until `GcHeap::invalidate_dead_ephemerons`
+    /// clears it because the key didn't survive a mark phase. See the
+    /// "Ephemerons" section of this module's docs. Pruned the same way as
+    /// `weak_refs`.
+    ephemerons: Mutex<Vec<Weak<Mutex<Option<(UntypedPointer, UntypedPointer)>>>>>,
+
+    /// Cumulative bytes moved into the heap by `try_alloc`, across the
+    /// heap's whole lifetime. See `HeapStats::bytes_allocated`.
+    bytes_allocated: u64,
+
+    /// Cumulative number of `gc_cycle`/`minor_collect` runs, across the
+    /// heap's whole lifetime. See `HeapStats::cycles_run`.
+    cycles_run: usize,
+
+    /// Objects reclaimed by the most recent `gc_cycle`/`minor_collect`,
+    /// gathered from `PageSet::sweep`/`minor_sweep`'s return value. See
+    /// `HeapStats::last_cycle_reclaimed`.
+    last_cycle_reclaimed: usize,
+
+    /// Called with a `CollectionSummary` just before and just after every
+    /// `gc_cycle`/`minor_collect`, so an embedder can log collection
+    /// pauses or adjust `GcHeapSession::set_page_limit` in response. See
+    /// `GcHeapSession::set_collect_callback`.
+    collect_callback: Option<Box<dyn FnMut(&CollectionSummary)>>,
+}
+
+/// A reference to a heap-allocated value that does not keep it alive and is
+/// not pinned, unlike `GcFrozenRef`. Created with `GcHeapSession::downgrade`;
+/// call `GcHeapSession::upgrade` to get a strong `T::Ref` back, which fails
+/// once the value has been collected.
+///
+/// `GcWeakRef` is skipped entirely by the mark phase. Its slot is
+/// invalidated by `GcHeap::invalidate_weak_refs`, which runs in `gc_cycle`
+/// and `minor_collect` after marking finishes (so every live object's mark bit is final) but
+/// before `sweep` reclaims anything.
+pub struct GcWeakRef<T> {
+    slot: Arc<Mutex<Option<UntypedPointer>>>,
+    heap_id: HeapId,
+    phantom: PhantomData<T>,
+}
+
+impl<T> Clone for GcWeakRef<T> {
+    fn clone(&self) -> Self {
+        GcWeakRef {
+            slot: self.slot.clone(),
+            heap_id: self.heap_id.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// A handle that keeps its target alive across any number of collections,
+/// without the pin count `GcRef` relies on (see the "root set" discussion
+/// in the `pages` module docs) and without giving up ownership of a real
+/// `T::Ref` the way `GcHeapSession::freeze` does. Meant for the handful of
+/// long-lived roots a host language keeps around across many `force_gc`
+/// calls -- a global environment, a symbol table -- in an ordinary Rust
+/// data structure, not just on the stack during one session method call.
+///
+/// Created with `GcHeapSession::root`; call `GcHeapSession::get_root` to
+/// get a `T::Ref` back out within a session. `Clone`ing a `Root` is cheap
+/// and shares the same underlying registration -- the target stays rooted
+/// until every clone has been dropped.
+pub struct Root<T> {
+    /// Behind a `Mutex` (rather than a bare `Arc<UntypedPointer>`) so
+    /// `GcHeap::fixup_forwarded_registries` can rewrite it in place when
+    /// `compact` relocates this root's unpinned target.
+    ptr: Arc<Mutex<UntypedPointer>>,
+    heap_id: HeapId,
+    phantom: PhantomData<T>,
+}
+
+impl<T> Clone for Root<T> {
+    fn clone(&self) -> Self {
+        Root {
+            ptr: self.ptr.clone(),
+            heap_id: self.heap_id.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// A weak key paired with a value that's retained only while the key is
+/// independently reachable -- the building block for a weak hash map. See
+/// the "Ephemerons" section of this module's docs. Created with
+/// `GcHeapSession::new_ephemeron`; call `GcHeapSession::ephemeron_value` to
+/// read the value back, which returns `None` once the key has died.
+pub struct GcEphemeronRef<K, V> {
+    slot: Arc<Mutex<Option<(UntypedPointer, UntypedPointer)>>>,
+    heap_id: HeapId,
+    phantom: PhantomData<(K, V)>,
+}
+
+impl<K, V> Clone for GcEphemeronRef<K, V> {
+    fn clone(&self) -> Self {
+        GcEphemeronRef {
+            slot: self.slot.clone(),
+            heap_id: self.heap_id.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+/// Per-type memory usage across an entire heap, keyed by the same `TypeId`
+/// cell-gc uses internally to distinguish heap types, plus heap-wide
+/// cumulative counters. See `GcHeapSession::heap_stats`.
+pub struct HeapStats {
+    by_type: HashMap<TypeId, TypeStats>,
+
+    /// Cumulative bytes moved into the heap by `try_alloc`, across the
+    /// heap's whole lifetime, not counting each allocation's `MarkWord`.
+    pub bytes_allocated: u64,
+
+    /// Cumulative number of `force_gc`/`minor_collect` cycles run so far.
+    pub cycles_run: usize,
+
+    /// Objects reclaimed by the most recent cycle, whether that was a
+    /// `force_gc` or a `minor_collect`. `0` before the first cycle runs.
+    pub last_cycle_reclaimed: usize,
+}
+
+impl HeapStats {
+    /// Stats for `T`, if this heap has a page set for `T` (created by the
+    /// first allocation of a `T`, or by a per-type setter such as
+    /// `GcHeapSession::set_page_limit`).
+    pub fn get<'h, T: IntoHeapAllocation<'h>>(&self) -> Option<TypeStats> {
+        self.by_type.get(&heap_type_id::<T>()).cloned()
+    }
+
+    /// Iterate over every type this heap has a page set for, in no particular
+    /// order. A type whose pages have all been freed is still included, with
+    /// a `page_count` of 0.
+    pub fn iter(&self) -> impl Iterator<Item = &TypeStats> {
+        self.by_type.values()
+    }
+}
+
+/// Which half of a collection cycle a `CollectionSummary` describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionPhase {
+    /// The cycle is about to start; `CollectionSummary::stats` reflects the
+    /// heap as it stands just before this cycle, including anything
+    /// allocated since the previous one.
+    Start,
+    /// The cycle (mark, sweep, and -- for a full cycle -- finalize and, if
+    /// enabled, compact) has just finished.
+    End,
+}
+
+/// Whether a `CollectionSummary` describes a full `force_gc` or a cheaper
+/// `minor_collect`. See `GcHeapSession::minor_collect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectionKind {
+    /// A `GcHeapSession::minor_collect`, or an automatic minor collection
+    /// triggered by `try_alloc` (see `GcHeapSession::set_generational_gc`).
+    Minor,
+    /// A `GcHeapSession::force_gc`, or an automatic full collection
+    /// triggered by `try_alloc`.
+    Full,
+}
+
+/// Passed to a `GcHeapSession::set_collect_callback` callback just before
+/// and just after every collection cycle, for logging collection pauses or
+/// driving adaptive tuning of `GcHeapSession::set_page_limit`.
+pub struct CollectionSummary {
+    /// Which half of the cycle this is.
+    pub phase: CollectionPhase,
+    /// Which kind of cycle this is.
+    pub kind: CollectionKind,
+    /// Heap-wide stats as of this callback. At `CollectionPhase::Start`
+    /// this is the heap just before the cycle, with
+    /// `HeapStats::last_cycle_reclaimed` still describing the previous
+    /// cycle; at
+    /// `CollectionPhase::End` it reflects the cycle that just ran,
+    /// including `HeapStats::last_cycle_reclaimed`.
+    pub stats: HeapStats,
 }
 
 unsafe impl Send for GcHeap {}
@@ -142,6 +398,49 @@ impl GcHeap {
             page_sets: HashMap::new(),
             marking_tracer: Some(MarkingTracer::default()),
             dropped_frozen_ptrs: Arc::new(Mutex::new(Vec::new())),
+            compacting: false,
+            #[cfg(feature = "provenance")]
+            provenance: HashMap::new(),
+            weak_refs: Mutex::new(Vec::new()),
+            roots: Mutex::new(Vec::new()),
+            generational_gc_threshold: None,
+            minor_collections_since_major: 0,
+            ephemerons: Mutex::new(Vec::new()),
+            bytes_allocated: 0,
+            cycles_run: 0,
+            last_cycle_reclaimed: 0,
+            collect_callback: None,
+        }
+    }
+
+    /// Aggregate per-type page/allocation stats across every `PageSet` in
+    /// this heap, plus the heap-wide cumulative counters. See
+    /// `pages::TypeStats` for what's reported per type.
+    fn heap_stats(&self) -> HeapStats {
+        let by_type = self.page_sets
+            .iter()
+            .map(|(&id, page_set)| (id, page_set.stats()))
+            .collect();
+        HeapStats {
+            by_type,
+            bytes_allocated: self.bytes_allocated,
+            cycles_run: self.cycles_run,
+            last_cycle_reclaimed: self.last_cycle_reclaimed,
+        }
+    }
+
+    /// Invoke the callback registered with `GcHeapSession::
+    /// set_collect_callback`, if any, with a fresh `HeapStats` snapshot.
+    ///
+    /// The callback is temporarily taken out of `self` while it runs, the
+    /// same way `with_marking_tracer` handles the marking tracer, so it
+    /// can't be called reentrantly and doesn't need `&mut` access to the
+    /// heap -- just the snapshot.
+    fn fire_collect_callback(&mut self, phase: CollectionPhase, kind: CollectionKind) {
+        if let Some(mut callback) = self.collect_callback.take() {
+            let stats = self.heap_stats();
+            callback(&CollectionSummary { phase, kind, stats });
+            self.collect_callback = Some(callback);
         }
     }
 
@@ -240,7 +539,8 @@ impl GcHeap {
         retval
     }
 
-    /// Clear all mark bits in preparation for GC.
+    /// Clear all mark bits in preparation for GC, seeding `roots` with every
+    /// pinned `GcRef`'s target plus every live `Root<T>`'s target.
     ///
     /// # Safety
     ///
@@ -249,6 +549,16 @@ impl GcHeap {
         for page_set in self.page_sets.values_mut() {
             page_set.clear_mark_bits(roots);
         }
+
+        self.roots.lock().unwrap().retain(|weak_ptr| {
+            match weak_ptr.upgrade() {
+                Some(slot) => {
+                    roots.push(*slot.lock().unwrap());
+                    true
+                }
+                None => false,
+            }
+        });
     }
 
     fn unpin_dropped_ptrs(&mut self) {
@@ -274,16 +584,281 @@ impl GcHeap {
         self.gc_cycle(false);
     }
 
+    /// Run whichever collection `try_alloc`'s out-of-pages fallback should
+    /// try next, per `generational_gc_threshold`. Returns `true` if a minor
+    /// collection ran, so the caller knows to escalate to a full collection
+    /// if that didn't free the slot it needed -- a minor collection can't
+    /// reclaim a young object kept alive only by an edge from an old one
+    /// outside the dirty-card roots it used, let alone anything already
+    /// promoted to old.
+    fn collect_for_allocation(&mut self) -> bool {
+        if let Some(threshold) = self.generational_gc_threshold {
+            if self.minor_collections_since_major < threshold {
+                self.minor_collections_since_major += 1;
+                self.minor_collect();
+                return true;
+            }
+        }
+        self.minor_collections_since_major = 0;
+        self.gc();
+        false
+    }
+
+    /// Run a minor collection: mark the real roots plus every old-to-young
+    /// edge recorded by the write barrier (see `pages::write_barrier`), then
+    /// sweep and promote only the young generation. Old pages are left
+    /// completely untouched.
+    ///
+    /// # Note
+    ///
+    /// The dirty-card roots are folded in by pinning them for the duration
+    /// of the mark phase, so today this still walks the whole live object
+    /// graph; the saving is in `sweep`, which skips old pages entirely
+    /// rather than rescanning their free lists and mark state. Making the
+    /// *mark* phase itself generation-aware (stopping at old objects that
+    /// have no dirty cards) is follow-up work for the tracer.
+    ///
+    /// Unlike `gc_cycle`, this does not run finalizers on unmarked young
+    /// objects (see `pages::PageSet::finalize_unmarked`); wiring that in
+    /// here is follow-up work.
+    pub fn minor_collect(&mut self) {
+        self.fire_collect_callback(CollectionPhase::Start, CollectionKind::Minor);
+        self.unpin_dropped_ptrs();
+
+        let mut dirty_roots = Vec::new();
+        for page_set in self.page_sets.values() {
+            page_set.collect_dirty_roots(&mut dirty_roots);
+        }
+        for &ptr in &dirty_roots {
+            unsafe {
+                pages::pin_untyped(ptr);
+            }
+        }
+
+        mark(self, false);
+
+        for &ptr in &dirty_roots {
+            unsafe {
+                pages::unpin_untyped(ptr);
+            }
+        }
+
+        unsafe {
+            self.invalidate_weak_refs();
+            self.invalidate_dead_ephemerons();
+        }
+
+        let _sp = signposts::Sweeping::new();
+        let mut reclaimed = 0;
+        for page_set in self.page_sets.values_mut() {
+            unsafe {
+                reclaimed += page_set.minor_sweep();
+            }
+        }
+        self.last_cycle_reclaimed = reclaimed;
+        self.cycles_run += 1;
+        self.fire_collect_callback(CollectionPhase::End, CollectionKind::Minor);
+    }
+
+    /// Invalidate every `GcWeakRef` slot whose target didn't survive the
+    /// mark phase that just finished, so a later `GcHeapSession::upgrade`
+    /// on it returns `None`. Also prunes entries whose `GcWeakRef` has
+    /// itself been dropped.
+    ///
+    /// # Safety
+    ///
+    /// Must be called only after a full mark phase and before `sweep`
+    /// reclaims anything.
+    unsafe fn invalidate_weak_refs(&mut self) {
+        let mut registry = self.weak_refs.lock().unwrap();
+        registry.retain(|weak_slot| {
+            let slot = match weak_slot.upgrade() {
+                Some(slot) => slot,
+                None => return false,
+            };
+            let mut guard = slot.lock().unwrap();
+            if let Some(ptr) = *guard {
+                if !pages::get_mark_bit_untyped(ptr) {
+                    *guard = None;
+                }
+            }
+            true
+        });
+    }
+
+    /// Every currently-registered ephemeron's `(key, value)` pointers, for
+    /// the fixpoint rescan `marking::mark` performs once ordinary
+    /// transitive marking reaches a fixpoint (see the "Ephemerons" section
+    /// of this module's docs). Dropped `GcEphemeronRef`s and already-
+    /// cleared slots are skipped.
+    pub(crate) fn ephemeron_targets(&self) -> Vec<(UntypedPointer, UntypedPointer)> {
+        self.ephemerons
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|weak_slot| weak_slot.upgrade())
+            .filter_map(|slot| *slot.lock().unwrap())
+            .collect()
+    }
+
+    /// Clear the value slot of every ephemeron whose key didn't survive the
+    /// mark phase that just finished (including its ephemeron fixpoint
+    /// pass), so the value becomes collectible if nothing else holds it.
+    /// Also prunes entries whose `GcEphemeronRef` has itself been dropped.
+    ///
+    /// # Safety
+    ///
+    /// Must be called only after a full mark phase (fixpoint included) and
+    /// before `sweep` reclaims anything.
+    unsafe fn invalidate_dead_ephemerons(&mut self) {
+        let mut registry = self.ephemerons.lock().unwrap();
+        registry.retain(|weak_slot| {
+            let slot = match weak_slot.upgrade() {
+                Some(slot) => slot,
+                None => return false,
+            };
+            let mut guard = slot.lock().unwrap();
+            if let Some((key, _)) = *guard {
+                if !pages::get_mark_bit_untyped(key) {
+                    *guard = None;
+                }
+            }
+            true
+        });
+    }
+
+    /// Rewrite every live `Root<T>`, `GcWeakRef`, and `GcEphemeronRef` slot
+    /// that `compact` left a forwarding pointer behind for, so
+    /// `GcHeapSession::get_root`/`upgrade`/`ephemeron_value` don't hand back
+    /// a pointer into a page `sweep` is about to reclaim. `invalidate_weak_refs`
+    /// and `invalidate_dead_ephemerons` already ran before `compact`, against
+    /// the pre-relocation addresses, so any slot that survived marking still
+    /// needs its pointer brought up to date here. Also prunes entries whose
+    /// handle has itself been dropped, the same way those two do.
+    ///
+    /// # Safety
+    ///
+    /// Must be called only after `compact` has finished relocating
+    /// everything and before `sweep` reclaims the pages objects were
+    /// relocated out of.
+    unsafe fn fixup_forwarded_registries(&mut self) {
+        self.roots.lock().unwrap().retain(|weak_ptr| {
+            let slot = match weak_ptr.upgrade() {
+                Some(slot) => slot,
+                None => return false,
+            };
+            let mut guard = slot.lock().unwrap();
+            if pages::is_forwarded_untyped(*guard) {
+                *guard = pages::forwarding_address_untyped(*guard);
+            }
+            true
+        });
+
+        self.weak_refs.lock().unwrap().retain(|weak_slot| {
+            let slot = match weak_slot.upgrade() {
+                Some(slot) => slot,
+                None => return false,
+            };
+            let mut guard = slot.lock().unwrap();
+            if let Some(ptr) = *guard {
+                if pages::is_forwarded_untyped(ptr) {
+                    *guard = Some(pages::forwarding_address_untyped(ptr));
+                }
+            }
+            true
+        });
+
+        self.ephemerons.lock().unwrap().retain(|weak_slot| {
+            let slot = match weak_slot.upgrade() {
+                Some(slot) => slot,
+                None => return false,
+            };
+            let mut guard = slot.lock().unwrap();
+            if let Some((key, value)) = *guard {
+                let key = if pages::is_forwarded_untyped(key) {
+                    pages::forwarding_address_untyped(key)
+                } else {
+                    key
+                };
+                let value = if pages::is_forwarded_untyped(value) {
+                    pages::forwarding_address_untyped(value)
+                } else {
+                    value
+                };
+                *guard = Some((key, value));
+            }
+            true
+        });
+    }
+
     fn gc_cycle(&mut self, dropping: bool) {
+        self.fire_collect_callback(CollectionPhase::Start, CollectionKind::Full);
         self.unpin_dropped_ptrs();
         mark(self, dropping);
 
+        // Give everything that didn't survive marking a chance to clean up
+        // (close file handles, etc.) before its memory is reclaimed or
+        // relocated by compaction. See the "Finalization" section of the
+        // `pages` module docs; finalizers must not allocate.
+        for page_set in self.page_sets.values() {
+            unsafe {
+                page_set.finalize_unmarked();
+            }
+        }
+
+        unsafe {
+            self.invalidate_weak_refs();
+            self.invalidate_dead_ephemerons();
+        }
+
+        if self.compacting {
+            let _sp = signposts::Compacting::new();
+            for page_set in self.page_sets.values_mut() {
+                unsafe {
+                    page_set.compact();
+                }
+            }
+
+            // `compact()` left forwarding pointers behind in every object it
+            // relocated. Re-trace every live edge and every root, rewriting
+            // any that target a forwarded object, before sweep reclaims the
+            // pages those objects used to live on.
+            self.with_marking_tracer(|heap, tracer| unsafe {
+                fixup_forwarded(heap, tracer);
+            });
+
+            unsafe {
+                self.fixup_forwarded_registries();
+            }
+        }
+
         let _sp = signposts::Sweeping::new();
+        let mut reclaimed = 0;
         for page_set in self.page_sets.values_mut() {
             unsafe {
-                page_set.sweep();
+                reclaimed += page_set.sweep();
             }
         }
+        self.last_cycle_reclaimed = reclaimed;
+
+        // A heap being dropped has no further use for its quarantines. Empty
+        // them, so pages that only hold quarantined slots count as empty.
+        if dropping {
+            for page_set in self.page_sets.values_mut() {
+                page_set.set_quarantine_budget(None);
+            }
+        }
+
+        // Everything still around just had its liveness verified from the
+        // real roots, so fold it all into the old generation: future minor
+        // collections don't need to re-examine it, only the dirty cards
+        // that get set from here on.
+        for page_set in self.page_sets.values_mut() {
+            page_set.promote_all_and_clear_cards();
+        }
+
+        self.cycles_run += 1;
+        self.fire_collect_callback(CollectionPhase::End, CollectionKind::Full);
     }
 
     fn is_empty(&self) -> bool {
@@ -329,6 +904,22 @@ impl<'h> GcHeapSession<'h> {
         self.get_page_set::<T>().set_page_limit(limit);
     }
 
+    /// Set the number of completely empty pages of `T` values that should be
+    /// kept around after a sweep instead of being freed back to the OS.
+    ///
+    /// By default this is 0: empty pages are released immediately. Raising
+    /// it trades some steady-state memory for fewer calls into the OS
+    /// allocator on workloads that allocate and free `T` values in bursts.
+    pub fn set_retain_pages<T: IntoHeapAllocation<'h>>(&mut self, n: usize) {
+        self.get_page_set::<T>().set_retain_pages(n);
+    }
+
+    /// Enable or disable the freed-object quarantine for `T` values (see
+    /// `pages::PageSet::set_quarantine_budget`). Off by default.
+    pub fn set_quarantine_budget<T: IntoHeapAllocation<'h>>(&mut self, budget: Option<usize>) {
+        self.get_page_set::<T>().set_quarantine_budget(budget);
+    }
+
     /// Allocate memory, moving `value` into the heap.
     ///
     /// If a limit has previously been set using `set_page_limit`, and we run
@@ -336,6 +927,7 @@ impl<'h> GcHeapSession<'h> {
     /// values, and they are all full of live values), `try_alloc` first
     /// attempts to free some memory by doing garbage collection. If that
     /// doesn't work, `try_alloc` returns `None`.
+    #[cfg_attr(feature = "provenance", track_caller)]
     pub fn try_alloc<T: IntoHeapAllocation<'h>>(&mut self, value: T) -> Option<T::Ref> {
         // For now, this is done very early, so that if it panics, the heap is
         // left in an OK state. Better wrapping of raw pointers would make it
@@ -353,16 +945,34 @@ impl<'h> GcHeapSession<'h> {
                     // doesn't know it exists.
                     let tmp_value = T::from_heap(&u);
                     drop(u);
-                    self.heap.gc();
+                    let ran_minor = self.heap.collect_for_allocation();
                     u = tmp_value.into_heap();
                     match self.get_page_set::<T>().try_alloc() {
                         Some(p) => p,
+                        // A minor collection only reclaims young objects, so
+                        // it may simply not have touched this type's pages
+                        // (or everything on them survived via an old-to-
+                        // young edge). Escalate to a full collection before
+                        // giving up.
+                        None if ran_minor => {
+                            let tmp_value = T::from_heap(&u);
+                            drop(u);
+                            self.heap.gc();
+                            u = tmp_value.into_heap();
+                            match self.get_page_set::<T>().try_alloc() {
+                                Some(p) => p,
+                                None => return None,
+                            }
+                        }
                         None => return None,
                     }
                 }
             };
 
             ptr::write(p.as_raw() as *mut _, u);
+            self.heap.bytes_allocated += mem::size_of::<T::In>() as u64;
+            #[cfg(feature = "provenance")]
+            self.heap.provenance.insert(p.as_raw() as usize, Location::caller());
             let gc_ref = T::wrap_gc_ref(GcRef::new(p));
             Some(gc_ref)
         }
@@ -385,6 +995,218 @@ impl<'h> GcHeapSession<'h> {
         self.heap.gc();
     }
 
+    /// Do a minor collection: mark and sweep only the young generation (see
+    /// `GcHeap::minor_collect`). Much cheaper than `force_gc` for heaps
+    /// dominated by long-lived objects, as long as inter-generational
+    /// pointers go through the macro-generated write barrier.
+    pub fn minor_collect(&mut self) {
+        self.heap.minor_collect();
+    }
+
+    /// Control whether `try_alloc`'s automatic collection, triggered by
+    /// `set_page_limit`, is allowed to run cheaper minor collections instead
+    /// of a full one every time.
+    ///
+    /// `None` (the default) means every automatic collection is a full
+    /// `force_gc`. `Some(n)` means up to `n` consecutive automatic
+    /// collections run `minor_collect` first; if one of those doesn't free
+    /// a slot for the type being allocated, `try_alloc` escalates to a full
+    /// collection right away regardless of how many minor collections are
+    /// left in the budget. Once `n` minor collections in a row have run,
+    /// the next automatic collection is a full one and the count resets.
+    ///
+    /// Has no effect on `force_gc`/`minor_collect` called directly -- only
+    /// on the implicit collection `try_alloc` runs when it hits a page
+    /// limit.
+    pub fn set_generational_gc(&mut self, threshold: Option<usize>) {
+        self.heap.generational_gc_threshold = threshold;
+    }
+
+    /// Enable or disable the compacting pass that runs between marking and
+    /// sweeping (see `pages::PageSet::compact`). Off by default.
+    ///
+    /// Turning this on relocates live, unpinned objects out of full pages
+    /// into non-full ones (see `pages::PageSet::compact` for which pages
+    /// qualify) so those pages can be freed, at the cost of extra copying and
+    /// edge fix-up work on every collection.
+    pub fn set_compacting_gc(&mut self, enabled: bool) {
+        self.heap.compacting = enabled;
+    }
+
+    /// Report page counts, live/free allocation counts, and per-value size
+    /// for every type that has been allocated in this heap so far, plus
+    /// heap-wide cumulative counters. See `HeapStats`.
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.heap_stats()
+    }
+
+    /// Register a callback invoked with a `CollectionSummary` just before
+    /// and just after every `force_gc`/`minor_collect` cycle, including
+    /// the automatic ones `try_alloc` runs when it hits a page limit. Pass
+    /// `None` to remove a previously-registered callback; only one
+    /// callback can be registered at a time, and setting a new one
+    /// replaces the old.
+    ///
+    /// The callback only ever sees a `CollectionSummary` snapshot, never a
+    /// `&mut GcHeap` or `GcHeapSession`, so there's no way to allocate or
+    /// trigger another collection from inside it. An embedder that wants
+    /// to call `set_page_limit` in response should stash what it needs
+    /// from the summary and act on it after the triggering method returns.
+    pub fn set_collect_callback<F>(&mut self, callback: Option<F>)
+    where
+        F: FnMut(&CollectionSummary) + 'static,
+    {
+        self.heap.collect_callback = callback.map(|cb| Box::new(cb) as Box<dyn FnMut(&CollectionSummary)>);
+    }
+
+    /// Where the allocation at `ptr` was made, if it's still live and this
+    /// was built with `--features provenance`. Intended for leak hunts:
+    /// enumerate survivors after a GC and group them by allocation site.
+    #[cfg(feature = "provenance")]
+    pub fn provenance_of<T: IntoHeapAllocation<'h>>(
+        &self,
+        ptr: Pointer<T::In>,
+    ) -> Option<&'static Location<'static>> {
+        self.heap.provenance.get(&(ptr.as_raw() as usize)).cloned()
+    }
+
+    /// Replace `T`'s page source with a memory-mapped view of `file`,
+    /// picking up whatever was saved there last, or starting empty if
+    /// `file` is freshly created. `file` is grown first if it has fewer
+    /// than `min_pages` page-sized regions to work with.
+    ///
+    /// # Safety / limitations
+    ///
+    /// cell-gc has no type id that survives a process restart -- a
+    /// `pages::TypeId` is itself a code pointer -- so there is deliberately
+    /// no on-disk type tag to check. This trusts the caller that `file` was
+    /// last saved by a `T` page set; getting that wrong corrupts memory.
+    /// Each call covers a single type's pages; stitching several types'
+    /// worth of pages into one file is follow-up work.
+    #[cfg(feature = "persistent")]
+    pub fn open_from_file<T: IntoHeapAllocation<'h>>(
+        &mut self,
+        file: &File,
+        min_pages: usize,
+    ) -> io::Result<()> {
+        let key = heap_type_id::<T>();
+        let heap: *mut GcHeap = self.heap;
+        let page_set = unsafe { PageSet::open::<T>(heap, file, min_pages)? };
+        self.heap.page_sets.insert(key, page_set);
+        Ok(())
+    }
+
+    /// Flush `T`'s pages to the file passed to `open_from_file`.
+    ///
+    /// # Panics
+    ///
+    /// If `T`'s pages aren't currently backed by a file.
+    #[cfg(feature = "persistent")]
+    pub fn save_to_file<T: IntoHeapAllocation<'h>>(&mut self) -> io::Result<()> {
+        self.get_page_set::<T>().flush()
+    }
+
+    /// Create a `GcWeakRef` pointing at `t` that does not keep it alive.
+    ///
+    /// This assumes `IntoHeapAllocation` grows an `as_untyped_ptr` method,
+    /// generated by `#[derive(IntoHeap)]` parallel to `wrap_gc_ref`, that
+    /// reads the raw pointer out of a `Ref` without pinning or consuming
+    /// it; the derive crate that would generate it isn't part of this
+    /// snapshot.
+    pub fn downgrade<T: IntoHeapAllocation<'h>>(&self, t: &T::Ref) -> GcWeakRef<T> {
+        let slot = Arc::new(Mutex::new(Some(T::as_untyped_ptr(t))));
+        self.heap.weak_refs.lock().unwrap().push(Arc::downgrade(&slot));
+        GcWeakRef {
+            slot,
+            heap_id: self.heap_id(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Upgrade a `GcWeakRef` back to a strong `T::Ref`, or `None` if its
+    /// target has been collected.
+    ///
+    /// # Panics
+    ///
+    /// If `w` was created from a different heap than this session's.
+    pub fn upgrade<T: IntoHeapAllocation<'h>>(&self, w: &GcWeakRef<T>) -> Option<T::Ref> {
+        self.check_heap_id(w.heap_id.clone());
+        let ptr = (*w.slot.lock().unwrap())?;
+        unsafe {
+            let typed = ptr.as_typed_ptr::<T::In>();
+            Some(T::wrap_gc_ref(GcRef::new(typed)))
+        }
+    }
+
+    /// Register `r` as a persistent root: unlike an ordinary `T::Ref`, the
+    /// returned `Root<T>` keeps `r`'s target alive across any number of
+    /// `force_gc`/`minor_collect` calls without pinning it, and can be
+    /// stashed in an ordinary Rust data structure instead of staying
+    /// confined to one session borrow. See `Root`'s docs.
+    pub fn root<T: IntoHeapAllocation<'h>>(&mut self, r: T::Ref) -> Root<T> {
+        let ptr = Arc::new(Mutex::new(T::as_untyped_ptr(&r)));
+        self.heap.roots.lock().unwrap().push(Arc::downgrade(&ptr));
+        Root {
+            ptr,
+            heap_id: self.heap_id(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Dereference a `Root<T>` back to a strong `T::Ref`, valid for this
+    /// session.
+    ///
+    /// # Panics
+    ///
+    /// If `root` was created from a different heap than this session's.
+    pub fn get_root<T: IntoHeapAllocation<'h>>(&self, root: &Root<T>) -> T::Ref {
+        self.check_heap_id(root.heap_id.clone());
+        unsafe {
+            let typed = root.ptr.lock().unwrap().as_typed_ptr::<T::In>();
+            T::wrap_gc_ref(GcRef::new(typed))
+        }
+    }
+
+    /// Create an ephemeron: `value` is retained only as long as `key` is
+    /// independently reachable. See the "Ephemerons" section of this
+    /// module's docs.
+    ///
+    /// Dropping the returned `GcEphemeronRef` (and every clone of it) is
+    /// the only other way for `value` to stop being retained through this
+    /// ephemeron; until then, a live key keeps it alive across collections.
+    pub fn new_ephemeron<K, V>(&self, key: &K::Ref, value: V::Ref) -> GcEphemeronRef<K, V>
+    where
+        K: IntoHeapAllocation<'h>,
+        V: IntoHeapAllocation<'h>,
+    {
+        let pair = (K::as_untyped_ptr(key), V::as_untyped_ptr(&value));
+        let slot = Arc::new(Mutex::new(Some(pair)));
+        self.heap.ephemerons.lock().unwrap().push(Arc::downgrade(&slot));
+        GcEphemeronRef {
+            slot,
+            heap_id: self.heap_id(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Read an ephemeron's value back, or `None` if its key has died.
+    ///
+    /// # Panics
+    ///
+    /// If `e` was created from a different heap than this session's.
+    pub fn ephemeron_value<K, V>(&self, e: &GcEphemeronRef<K, V>) -> Option<V::Ref>
+    where
+        K: IntoHeapAllocation<'h>,
+        V: IntoHeapAllocation<'h>,
+    {
+        self.check_heap_id(e.heap_id.clone());
+        let (_, value) = (*e.slot.lock().unwrap())?;
+        unsafe {
+            let typed = value.as_typed_ptr::<V::In>();
+            Some(V::wrap_gc_ref(GcRef::new(typed)))
+        }
+    }
+
     pub fn freeze<T: IntoHeapAllocation<'h>>(&self, t: T::Ref) -> GcFrozenRef<T> {
         GcFrozenRef::new(&self, t)
     }
@@ -1,13 +1,73 @@
 //! Allocating pages of memory from the OS and carving them into individual
 //! allocations. See TypedPage for details.
+//!
+//! ### Generations
+//!
+//! Pages (and the allocations on them) are additionally tagged young or old;
+//! see `PageHeader::young` and `MarkWord`'s `OLD_GENERATION_BIT`. This is
+//! only sound as long as one invariant holds: **every old object that is
+//! made to point at a young object must leave its containing page's card
+//! dirty**, by calling `write_barrier`. `GcHeap::minor_collect` relies on
+//! the dirty cards to find old-to-young edges without rescanning the old
+//! generation; if a write barrier is ever skipped, a minor collection can
+//! free a young object that's still reachable.
+//!
+//! Nothing runs a minor collection on its own, though: `try_alloc`'s
+//! automatic fallback always does a full `gc_cycle` unless the heap opts in
+//! via `GcHeapSession::set_generational_gc`, which lets a run of minor
+//! collections stand in for most of those before the next full one.
+//!
+//! ### Finalization
+//!
+//! `GcHeap::gc_cycle` gives heap types a safe alternative to `Drop` for
+//! cleanup that needs to run before an allocation's memory goes away --
+//! closing a file handle, say -- without the reentrancy hazards a real
+//! `Drop` impl invites (see the safety discussion at the top of `heap.rs`).
+//! Right after marking finishes, and before `compact`/`sweep` reclaim or
+//! relocate anything, every allocation that didn't survive has its
+//! `Finalize::finalize` run via `finalize_entry_point`, which rebuilds the
+//! value through `from_heap` rather than handing out a direct reference to
+//! the in-heap bytes. Finalizers must not allocate: the heap is frozen for
+//! the whole pass, so a nested allocation would have nowhere to go.
+//!
+//! ### Panic safety
+//!
+//! `drop_in_place` during `TypedPage::sweep` and `finalize_fn` during
+//! `PageHeader::finalize_unmarked` both run arbitrary user code (`Drop` and
+//! `Finalize` respectively), either of which can panic. Each slot's
+//! mark/allocated bits are updated before its destructor runs, not after, so
+//! a panicking drop never leaves a slot stuck looking allocated with no
+//! value behind it. Both `sweep` and `finalize_unmarked` catch the unwind
+//! themselves, finish working through the rest of the page, and only
+//! re-raise (the first) panic once every slot has been dealt with -- so one
+//! bad `Drop` or `Finalize` can't leave the page, or `GcHeap::drop`'s
+//! `all_pages_are_empty` assertion, in a bad state.
+//!
+//! ### Persistence (`--features persistent`)
+//!
+//! Where a `PageSet`'s pages come from is abstracted behind the `PageStore`
+//! trait. The default, `HeapPageStore`, carves them out of the process heap
+//! exactly as always. `MappedPageStore` instead backs them with a
+//! memory-mapped file, so a `GcHeapSession::save_to_file`/`open_from_file`
+//! pair can persist an object graph and reload it later, possibly at a
+//! different base address. Reloading a mapping that landed somewhere new
+//! means every pointer that was written while it lived at the old address
+//! -- page links, freelists, and inter-object edges alike -- is stale by a
+//! uniform `delta`; see `PageHeader::rebase` and `PageSet::relocate`.
 
 use heap::{GcHeap, HeapSessionId};
 use marking::MarkingTracer;
 use ptr::{Pointer, UntypedPointer};
-use std::{cmp, mem, ptr};
+#[cfg(feature = "persistent")]
+use std::fs::File;
+#[cfg(feature = "persistent")]
+use std::io;
+use std::{cmp, mem, ptr, slice};
+use std::collections::{HashSet, VecDeque};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
-use traits::{IntoHeapAllocation, Tracer};
+use std::panic::{self, AssertUnwindSafe};
+use traits::{Finalize, IntoHeapAllocation, Tracer};
 
 
 /// Stores mark bits, pin counts, and an "am I in use?" bit for heap allocations.
@@ -16,6 +76,27 @@ struct MarkWord(usize);
 const MARK_BIT: usize = 1;
 const ALLOCATED_BIT: usize = 2;
 
+/// Set by the compactor on an allocation it has relocated elsewhere; the new
+/// address is then stored in the allocation's (now-vacated) value slot. We
+/// steal the top bit of the word for this rather than a low bit, since low
+/// bits are already spoken for by `MARK_BIT`/`ALLOCATED_BIT` and the pin
+/// count (see `is_pinned`), and a pin count realistically never climbs high
+/// enough to collide with it.
+const FORWARDED_BIT: usize = 1 << (8 * mem::size_of::<usize>() - 1);
+
+/// Set once an allocation has survived a minor collection (see
+/// `GcHeap::minor_collect`) and so has been promoted to the old generation.
+/// Like `FORWARDED_BIT`, this steals a high bit out of the pin-count range
+/// rather than a low one.
+const OLD_GENERATION_BIT: usize = 1 << (8 * mem::size_of::<usize>() - 2);
+
+/// Bits of a `MarkWord` that actually hold the pin count, i.e. everything
+/// except `MARK_BIT`, `ALLOCATED_BIT`, and the two high bits `FORWARDED_BIT`
+/// and `OLD_GENERATION_BIT` steal from the top of the count's range. Used by
+/// `MarkWord::is_pinned` so that forwarding or promoting an allocation can't
+/// be mistaken for it having been pinned.
+const PIN_COUNT_MASK: usize = !(MARK_BIT | ALLOCATED_BIT | FORWARDED_BIT | OLD_GENERATION_BIT);
+
 /// Add the value `*p` to the root set, protecting it from GC.
 ///
 /// A value that has been pinned *n* times stays in the root set
@@ -46,14 +127,82 @@ pub unsafe fn unpin_untyped(p: UntypedPointer) {
     MarkWord::from_untyped_ptr(p, |mw| mw.unpin());
 }
 
+/// Pin a heap allocation (see `pin`), given an untyped pointer to it.
+///
+/// # Safety
+///
+/// `p` must point to a live allocation in this heap.
+pub unsafe fn pin_untyped(p: UntypedPointer) {
+    MarkWord::from_untyped_ptr(p, |mw| mw.pin());
+}
+
 pub unsafe fn get_mark_bit<U>(p: Pointer<U>) -> bool {
     MarkWord::from_ptr(p, |mw| mw.is_marked())
 }
 
+/// Read the mark bit of a heap allocation, given an untyped pointer to it.
+/// Used by `GcHeap::invalidate_weak_refs` to check whether a `GcWeakRef`'s
+/// target survived marking, without needing to know its concrete type.
+///
+/// # Safety
+///
+/// `p` must point to a live allocation in this heap.
+pub unsafe fn get_mark_bit_untyped(p: UntypedPointer) -> bool {
+    MarkWord::from_untyped_ptr(p, |mw| mw.is_marked())
+}
+
 pub unsafe fn set_mark_bit<U>(p: Pointer<U>) {
     MarkWord::from_ptr(p, |mw| mw.mark());
 }
 
+/// True if `compact` has relocated the allocation at `p` elsewhere.
+///
+/// # Safety
+///
+/// `p` must point to a live (possibly forwarded) allocation in this heap.
+pub(crate) unsafe fn is_forwarded<U>(p: Pointer<U>) -> bool {
+    MarkWord::from_ptr(p, |mw| mw.is_forwarded())
+}
+
+/// Read the new address that `compact` recorded for the allocation at `p`.
+///
+/// # Safety
+///
+/// `p` must point to an allocation for which `is_forwarded` returns true.
+pub(crate) unsafe fn forwarding_address<U>(p: Pointer<U>) -> Pointer<U> {
+    Pointer::new(*(p.as_raw() as *const *mut U))
+}
+
+/// Clear a forwarding pointer once every edge into it has been fixed up.
+///
+/// # Safety
+///
+/// `p` must point to a forwarded allocation in this heap.
+pub(crate) unsafe fn clear_forwarded<U>(p: Pointer<U>) {
+    MarkWord::from_ptr(p, |mw| mw.clear_forwarded());
+}
+
+/// Like `is_forwarded`, for a pointer whose pointee type isn't known. Used
+/// by `GcHeap::fixup_forwarded_registries` to fix up a `Root<T>`'s stored
+/// `UntypedPointer` after `compact` relocates its target.
+///
+/// # Safety
+///
+/// `p` must point to a live (possibly forwarded) allocation in this heap.
+pub(crate) unsafe fn is_forwarded_untyped(p: UntypedPointer) -> bool {
+    MarkWord::from_untyped_ptr(p, |mw| mw.is_forwarded())
+}
+
+/// Like `forwarding_address`, for a pointer whose pointee type isn't known.
+///
+/// # Safety
+///
+/// `p` must point to an allocation for which `is_forwarded_untyped` returns
+/// true.
+pub(crate) unsafe fn forwarding_address_untyped(p: UntypedPointer) -> UntypedPointer {
+    UntypedPointer::new(*(p.as_usize() as *const *const ()))
+}
+
 const MARK_WORD_INIT: MarkWord = MarkWord(0);
 
 impl MarkWord {
@@ -96,7 +245,7 @@ impl MarkWord {
     }
 
     fn is_pinned(&self) -> bool {
-        self.0 >> 2 != 0
+        self.0 & PIN_COUNT_MASK != 0
     }
 
     fn pin(&mut self) {
@@ -109,6 +258,56 @@ impl MarkWord {
         debug_assert!(self.is_pinned());
         self.0 -= 4;
     }
+
+    fn is_forwarded(&self) -> bool {
+        self.0 & FORWARDED_BIT != 0
+    }
+
+    fn set_forwarded(&mut self) {
+        debug_assert!(!self.is_pinned());
+        self.0 |= FORWARDED_BIT;
+    }
+
+    fn clear_forwarded(&mut self) {
+        self.0 &= !FORWARDED_BIT;
+    }
+
+    /// True once this allocation has survived a minor collection and been
+    /// promoted to the old generation. Freshly allocated objects start out
+    /// young (this bit clear).
+    fn is_old(&self) -> bool {
+        self.0 & OLD_GENERATION_BIT != 0
+    }
+
+    fn set_old(&mut self) {
+        self.0 |= OLD_GENERATION_BIT;
+    }
+}
+
+/// Size, in bytes, of one card in a page's dirty-card bitmap (see
+/// `PageHeader::dirty_cards`). Chosen so that a full page's worth of cards
+/// fits in a single byte.
+const CARD_SIZE: usize = PAGE_SIZE / 8;
+
+/// Record a write of `target` into a field of the old-generation object
+/// `container`. Must be called by every macro-generated setter immediately
+/// after the store, so that `GcHeap::minor_collect` can find this edge
+/// without re-scanning the whole old generation.
+///
+/// If `container` turns out to be young, or `target` is also old, this is a
+/// no-op: only old-pointing-at-young edges need to be remembered, since a
+/// minor collection never reclaims old objects and young objects are always
+/// scanned from the real roots anyway.
+///
+/// # Safety
+///
+/// `container` and `target` must point to live allocations in the same heap.
+pub unsafe fn write_barrier(container: UntypedPointer, target: UntypedPointer) {
+    let container_is_old = MarkWord::from_untyped_ptr(container, |mw| mw.is_old());
+    let target_is_young = !MarkWord::from_untyped_ptr(target, |mw| mw.is_old());
+    if container_is_old && target_is_young {
+        (*PageHeader::find(container)).mark_card_dirty(container);
+    }
 }
 
 /// Non-inlined function that serves as an entry point to marking. This is used
@@ -133,17 +332,79 @@ where
     }
 }
 
+/// Non-inlined entry point that runs one unmarked allocation's finalizer
+/// (see `Finalize`) during `GcHeap::gc_cycle`, before its page is swept.
+/// Rebuilds the value via `from_heap` -- the same path `try_alloc` uses to
+/// read a value back out before a GC -- rather than handing out a direct
+/// reference to the in-heap bytes, so a finalizer can never observe a
+/// half-swept page.
+///
+/// This assumes `IntoHeapAllocation` requires `Self: Finalize`, with
+/// `#[derive(IntoHeap)]` generating the default no-op impl unless the user
+/// writes their own by hand; the derive crate that would generate it isn't
+/// part of this snapshot.
+unsafe fn finalize_entry_point<'h, T>(addr: UntypedPointer)
+where
+    T: IntoHeapAllocation<'h>,
+{
+    let addr = addr.as_typed_ptr::<T::In>();
+    T::from_heap(addr.as_ref()).finalize();
+}
+
+/// Non-inlined entry point for `PageHeader::rebase`, stored as each page's
+/// `rebase_fn` so it can be called without knowing `T` at the call site.
+///
+/// This assumes `IntoHeapAllocation` grows a `rebase` method generated by
+/// `#[derive(IntoHeap)]`, parallel to `trace`, that shifts every raw pointer
+/// field of a value by a given address delta; the derive crate that would
+/// generate it isn't part of this snapshot.
+#[cfg(feature = "persistent")]
+unsafe fn rebase_entry_point<'h, T>(addr: UntypedPointer, delta: isize)
+where
+    T: IntoHeapAllocation<'h>,
+{
+    T::rebase(addr.as_typed_ptr::<T::In>().as_raw() as *mut T::In, delta);
+}
+
 /// A unique id for each type that implements `IntoHeapAllocation`.
 ///
 /// Implementation note: function types don't support Eq, so we cast to a
 /// meaningless pointer type.
-#[derive(Debug, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct TypeId(*const ());
 
 pub fn heap_type_id<'h, T: IntoHeapAllocation<'h>>() -> TypeId {
     TypeId(mark_entry_point::<T> as *const ())
 }
 
+/// A snapshot of one `PageSet`'s memory usage, returned by `PageSet::stats`
+/// and aggregated (keyed by `TypeId`) into `GcHeap::heap_stats`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TypeStats {
+    /// Number of pages allocated for this type.
+    pub page_count: usize,
+
+    /// Number of slots across those pages currently holding a live value.
+    pub live_count: usize,
+
+    /// Number of slots across those pages that are free (on a freelist, or
+    /// queued in the quarantine).
+    pub free_count: usize,
+
+    /// Size in bytes of one value of this type, not counting its `MarkWord`.
+    pub bytes_per_allocation: usize,
+
+    /// How many more pages could be allocated for this type before hitting
+    /// the limit set by `PageSet::set_page_limit`/`GcHeapSession::
+    /// set_page_limit`, or `None` if no limit is set.
+    pub pages_until_limit: Option<usize>,
+}
+
+/// If more than this fraction of a page's live objects are pinned, `compact`
+/// leaves the page alone rather than evacuating its few movable objects.
+const COMPACTION_PIN_THRESHOLD_NUM: usize = 1;
+const COMPACTION_PIN_THRESHOLD_DEN: usize = 4;
+
 pub(crate) const PAGE_SIZE: usize = 0x1000;
 
 /// We rely on all bits to the right of this bit being 0 in addresses of
@@ -154,12 +415,191 @@ fn is_aligned(ptr: *const ()) -> bool {
     ptr as usize & (PAGE_ALIGN - 1) == 0
 }
 
+/// Where a `PageSet` gets the backing memory for its pages, and what happens
+/// to that memory once a page is no longer needed.
+pub(crate) trait PageStore {
+    /// Allocate one `PAGE_ALIGN`-aligned region of `PAGE_SIZE` bytes. Its
+    /// contents are not specified; the caller initializes the page.
+    unsafe fn alloc_page(&mut self) -> *mut ();
+
+    /// Give back a page previously returned by `alloc_page` on this same store.
+    unsafe fn dealloc_page(&mut self, page: *mut ());
+
+    /// Downcast to `MappedPageStore`, if this store is file-backed. Lets
+    /// `PageSet::flush`/`open` reach persistence-only operations without
+    /// every `PageStore` impl having to deal with them.
+    #[cfg(feature = "persistent")]
+    fn as_mapped_mut(&mut self) -> Option<&mut MappedPageStore> {
+        None
+    }
+}
+
+/// The default `PageStore`: pages come from the process heap via `Vec<u8>`
+/// and disappear when freed, exactly as before page sourcing was made
+/// pluggable.
+pub(crate) struct HeapPageStore;
+
+impl PageStore for HeapPageStore {
+    unsafe fn alloc_page(&mut self) -> *mut () {
+        let mut vec: Vec<u8> = Vec::with_capacity(PAGE_SIZE);
+        let raw_page = vec.as_mut_ptr() as *mut ();
+        // Remove the memory from the vector; the caller now owns it and
+        // will give it back via `dealloc_page`.
+        mem::forget(vec);
+        raw_page
+    }
+
+    unsafe fn dealloc_page(&mut self, page: *mut ()) {
+        Vec::from_raw_parts(page as *mut u8, 0, PAGE_SIZE);
+    }
+}
+
+/// Fixed-size header written at the start of a persistent page file, so that
+/// a later `open_from_file` can find where the live pages start and how far
+/// the mapping has moved since it was saved. Occupies the first `PAGE_SIZE`
+/// bytes of the file; real pages start immediately after it. A zero `base`
+/// means the file is freshly created and has no pages yet.
+#[cfg(feature = "persistent")]
+#[repr(C)]
+struct Superblock {
+    base: usize,
+    full_pages: usize,
+    other_pages: usize,
+}
+
+/// A `PageStore` backed by a memory-mapped file, so a `PageSet`'s pages can
+/// be flushed to disk with `GcHeapSession::save_to_file` and reloaded later
+/// with `GcHeapSession::open_from_file`, possibly at a different base
+/// address. Pages are handed out by bumping `next_offset` through the
+/// mapping (past the reserved `Superblock` page) and never reused within a
+/// session; reclaiming the space freed by a dead object is left to a future
+/// compacting save.
+#[cfg(feature = "persistent")]
+pub(crate) struct MappedPageStore {
+    mmap: ::memmap::MmapMut,
+    next_offset: usize,
+}
+
+#[cfg(feature = "persistent")]
+impl MappedPageStore {
+    /// Memory-map `file` for read/write, growing it first if it's smaller
+    /// than `min_pages` page-sized regions plus the superblock.
+    fn new(file: &File, min_pages: usize) -> io::Result<MappedPageStore> {
+        let needed = ((min_pages + 1) * PAGE_SIZE) as u64;
+        if file.metadata()?.len() < needed {
+            file.set_len(needed)?;
+        }
+        let mmap = unsafe { ::memmap::MmapMut::map_mut(file)? };
+        assert!(
+            is_aligned(mmap.as_ptr() as *const ()),
+            "OS did not give us a page-aligned mapping"
+        );
+        Ok(MappedPageStore { mmap, next_offset: PAGE_SIZE })
+    }
+
+    fn base(&self) -> usize {
+        self.mmap.as_ptr() as usize
+    }
+
+    fn superblock(&self) -> *mut Superblock {
+        self.mmap.as_ptr() as *mut Superblock
+    }
+
+    /// Record where this type's page lists currently start and flush the
+    /// mapping to disk.
+    fn flush(&mut self, full_pages: *mut PageHeader, other_pages: *mut PageHeader) -> io::Result<()> {
+        let base = self.base();
+        let offset_of = |p: *mut PageHeader| if p.is_null() { 0 } else { p as usize - base };
+        unsafe {
+            let sb = self.superblock();
+            (*sb).base = base;
+            (*sb).full_pages = offset_of(full_pages);
+            (*sb).other_pages = offset_of(other_pages);
+        }
+        self.mmap.flush()
+    }
+
+    /// The page lists and address shift recorded the last time this mapping
+    /// (or the file it's backed by) was flushed: `(full_pages, other_pages,
+    /// new_base - old_base)`. All three are meaningless (and ignored by the
+    /// caller) when the file is freshly created.
+    fn saved_pages(&self) -> (*mut PageHeader, *mut PageHeader, isize) {
+        let base = self.base();
+        unsafe {
+            let sb = self.superblock();
+            if (*sb).base == 0 {
+                return (ptr::null_mut(), ptr::null_mut(), 0);
+            }
+            let delta = base as isize - (*sb).base as isize;
+            let at = |offset: usize| {
+                if offset == 0 {
+                    ptr::null_mut()
+                } else {
+                    (base + offset) as *mut PageHeader
+                }
+            };
+            (at((*sb).full_pages), at((*sb).other_pages), delta)
+        }
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl PageStore for MappedPageStore {
+    unsafe fn alloc_page(&mut self) -> *mut () {
+        assert!(
+            self.next_offset + PAGE_SIZE <= self.mmap.len(),
+            "MappedPageStore out of preallocated space; grow the file before allocating more pages"
+        );
+        let page = self.mmap.as_mut_ptr().add(self.next_offset) as *mut ();
+        self.next_offset += PAGE_SIZE;
+        page
+    }
+
+    unsafe fn dealloc_page(&mut self, _page: *mut ()) {
+        // Deliberately a no-op: an in-use mapping can't safely shrink out
+        // from under other pages, so freed space just sits idle in the file
+        // until the next save.
+    }
+
+    fn as_mapped_mut(&mut self) -> Option<&mut MappedPageStore> {
+        Some(self)
+    }
+}
+
 pub struct PageHeader {
     pub heap: *mut GcHeap,
     next_page: *mut PageHeader,
     mark_fn: unsafe fn(UntypedPointer, &mut MarkingTracer),
+
+    /// Rebases the inter-object pointer fields of one live value of this
+    /// page's type by a given address delta. Only present with
+    /// `--features persistent`; see `PageHeader::rebase`.
+    #[cfg(feature = "persistent")]
+    rebase_fn: unsafe fn(UntypedPointer, isize),
     freelist: *mut (),
     allocation_size: usize,
+
+    /// True if this page belongs to the young generation. Pages start out
+    /// young; a major collection (`GcHeap::gc`) promotes every surviving
+    /// page to old in one go, since at that point everything reachable has
+    /// just been verified live from the real roots.
+    young: bool,
+
+    /// One bit per `CARD_SIZE`-byte card of this page, set by
+    /// `mark_card_dirty` (via `write_barrier`) when an old object on this
+    /// page is made to point at a young one. Consulted by
+    /// `GcHeap::minor_collect` to find old-to-young edges without
+    /// rescanning the whole old generation. Only meaningful when `!young`.
+    dirty_cards: u8,
+
+    /// Number of this page's slots currently parked in `PageSet::
+    /// quarantine` rather than back on `freelist`. `ALLOCATED_BIT` is
+    /// already clear for these (`TypedPage::sweep` clears it before
+    /// calling `on_free`), so without this count `is_empty` would see a page as
+    /// releasable while `Quarantine` still holds pointers onto it --
+    /// freeing the page out from under them. Kept in sync by `Quarantine::
+    /// enqueue` and `PageHeader::add_to_free_list_raw`.
+    quarantined_count: usize,
 }
 
 impl PageHeader {
@@ -220,8 +660,52 @@ impl PageHeader {
         }
     }
 
-    /// True if nothing on this page is allocated.
+    /// Call `finalize_fn` on every allocated-but-unmarked slot on this page,
+    /// in address order. See the "Finalization" section of the module docs.
+    ///
+    /// A user `Finalize` impl can panic. If one does, this keeps finalizing
+    /// the rest of the page rather than abandoning it half-finalized -- see
+    /// the "Panic safety" section of the module docs -- and re-raises the
+    /// first panic only once every slot has been accounted for.
+    ///
+    /// # Safety
+    ///
+    /// Must run after a full mark phase and before this page's memory is
+    /// reclaimed or relocated. `finalize_fn` must be valid for this page's
+    /// type.
+    unsafe fn finalize_unmarked(&self, finalize_fn: unsafe fn(UntypedPointer)) {
+        let mut addr = self.begin();
+        let end = self.end();
+        let mut panicked: Option<Box<dyn std::any::Any + Send + 'static>> = None;
+        while addr < end {
+            let mark_word = &*(addr as *const MarkWord);
+            if mark_word.is_allocated() && !mark_word.is_marked() {
+                let ptr = UntypedPointer::new((addr + mem::size_of::<MarkWord>()) as *const ());
+                if let Err(payload) =
+                    panic::catch_unwind(AssertUnwindSafe(|| finalize_fn(ptr)))
+                {
+                    if panicked.is_none() {
+                        panicked = Some(payload);
+                    }
+                }
+            }
+            addr += self.allocation_size;
+        }
+
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    /// True if nothing on this page is allocated and nothing on it is still
+    /// sitting in `PageSet::quarantine` either -- a page can clear every
+    /// `ALLOCATED_BIT` during `sweep` yet still have live pointers into it
+    /// held by the quarantine, and releasing it back to the allocator out
+    /// from under those would be a use-after-free.
     pub fn is_empty(&self) -> bool {
+        if self.quarantined_count != 0 {
+            return false;
+        }
         let mut addr = self.begin();
         let end = self.end();
         while addr < end {
@@ -233,8 +717,172 @@ impl PageHeader {
         }
         true
     }
+
+    /// Number of allocated slots on this page.
+    fn live_count(&self) -> usize {
+        let mut addr = self.begin();
+        let end = self.end();
+        let mut count = 0;
+        while addr < end {
+            let mark_word = unsafe { &*(addr as *const MarkWord) };
+            if mark_word.is_allocated() {
+                count += 1;
+            }
+            addr += self.allocation_size;
+        }
+        count
+    }
+
+    /// Total number of allocation slots on this page, allocated or not.
+    fn slot_count(&self) -> usize {
+        (self.end() - self.begin()) / self.allocation_size
+    }
+
+    /// Number of allocated slots on this page that are pinned, and therefore
+    /// cannot be relocated by the compactor.
+    fn pinned_count(&self) -> usize {
+        let mut addr = self.begin();
+        let end = self.end();
+        let mut count = 0;
+        while addr < end {
+            let mark_word = unsafe { &*(addr as *const MarkWord) };
+            if mark_word.is_allocated() && mark_word.is_pinned() {
+                count += 1;
+            }
+            addr += self.allocation_size;
+        }
+        count
+    }
+
+    /// Mark the card containing `ptr` dirty. `ptr` must point somewhere
+    /// inside this page.
+    fn mark_card_dirty(&mut self, ptr: UntypedPointer) {
+        let offset = ptr.as_usize() - (self as *const PageHeader as usize);
+        let card = offset / CARD_SIZE;
+        self.dirty_cards |= 1 << card;
+    }
+
+    /// Append the address of every allocated slot in a dirty card of this
+    /// (old-generation) page to `roots`. These are the old objects that the
+    /// write barrier recorded as possibly pointing into the young
+    /// generation; `GcHeap::minor_collect` treats them as extra roots.
+    fn dirty_card_objects(&self, roots: &mut Vec<UntypedPointer>) {
+        if self.young || self.dirty_cards == 0 {
+            return;
+        }
+        let base = self as *const PageHeader as usize;
+        let mut addr = self.begin();
+        let end = self.end();
+        while addr < end {
+            let card = (addr - base) / CARD_SIZE;
+            if self.dirty_cards & (1 << card) != 0 {
+                let mark_word = unsafe { &*(addr as *const MarkWord) };
+                if mark_word.is_allocated() {
+                    let ptr = unsafe {
+                        UntypedPointer::new((addr + mem::size_of::<MarkWord>()) as *const ())
+                    };
+                    roots.push(ptr);
+                }
+            }
+            addr += self.allocation_size;
+        }
+    }
+
+    /// Try to hand back a free slot on this page, without regard to its
+    /// type. Used by the compactor, which moves raw bytes around rather
+    /// than going through `TypedPage::try_alloc`.
+    unsafe fn try_alloc_raw(&mut self) -> Option<*mut ()> {
+        let p = self.freelist;
+        if p.is_null() {
+            return None;
+        }
+        let listp = p as *mut *mut ();
+        self.freelist = *listp;
+        let mw = &mut *((p as usize - mem::size_of::<MarkWord>()) as *mut MarkWord);
+        debug_assert!(!mw.is_allocated());
+        mw.set_allocated();
+        // This slot is about to receive a live object relocated by
+        // `compact` (the only caller). Mark it too, not just allocate it --
+        // the mark phase that produced the object being moved has already
+        // run, and `sweep` later in the same cycle reclaims anything
+        // allocated-but-unmarked, which would otherwise destroy the object
+        // in the very cycle that relocated it.
+        mw.mark();
+        Some(p)
+    }
+
+    /// Push a previously-quarantined slot back onto the ordinary freelist,
+    /// without regard to its type. `p` must not already be allocated.
+    fn add_to_free_list_raw(&mut self, p: UntypedPointer) {
+        debug_assert!(self.quarantined_count > 0);
+        self.quarantined_count -= 1;
+        unsafe {
+            let listp = p.as_usize() as *mut *mut ();
+            *listp = self.freelist;
+            self.freelist = listp as *mut ();
+        }
+    }
+
+    /// Shift every pointer on this page that targets somewhere else in the
+    /// same mapping by `delta`, and point `heap` at `new_heap` instead of
+    /// rebasing it -- `GcHeap` lives in ordinary (non-mapped) memory, so it
+    /// has nothing to do with where this mapping landed.
+    ///
+    /// Shifted: the intrusive freelist chain (every link lives on this same
+    /// page), `next_page`, and every live value's inter-object pointer
+    /// fields, via `rebase_fn`. `rebase_fn`/`mark_fn`/`allocation_size` must
+    /// already have been reset to match the type being reopened -- see
+    /// `PageSet::relocate` -- since the ones saved in the file are raw code
+    /// pointers from a previous process and cannot be trusted.
+    ///
+    /// # Safety
+    ///
+    /// `rebase_fn` must be valid for every live value on this page.
+    #[cfg(feature = "persistent")]
+    unsafe fn rebase(&mut self, new_heap: *mut GcHeap, delta: isize) {
+        let shift = |p: *mut ()| ((p as isize) + delta) as *mut ();
+
+        self.heap = new_heap;
+        if !self.next_page.is_null() {
+            self.next_page = shift(self.next_page as *mut ()) as *mut PageHeader;
+        }
+
+        // `self.freelist` and the "next" pointer stored in each link are
+        // stale addresses from the old mapping; `shift` turns a stale
+        // address into the live one in *this* mapping, which is what we
+        // must actually dereference.
+        let mut stale_link = self.freelist;
+        if !stale_link.is_null() {
+            self.freelist = shift(stale_link);
+        }
+        while !stale_link.is_null() {
+            let listp = shift(stale_link) as *mut *mut ();
+            let stale_next = *listp;
+            if !stale_next.is_null() {
+                *listp = shift(stale_next);
+            }
+            stale_link = stale_next;
+        }
+
+        let mut addr = self.begin();
+        let end = self.end();
+        while addr < end {
+            let mark_word = &*(addr as *const MarkWord);
+            if mark_word.is_allocated() {
+                let value = UntypedPointer::new((addr + mem::size_of::<MarkWord>()) as *const ());
+                (self.rebase_fn)(value, delta);
+            }
+            addr += self.allocation_size;
+        }
+    }
 }
 
+/// The byte value swept, unreachable memory is painted with under
+/// debug/test builds (see `TypedPage::sweep`), and that the quarantine
+/// subsystem (see `PageSet::quarantine`) checks is still intact before
+/// handing a slot back out.
+const SWEPT_BYTE: u8 = 0xf4;
+
 /// A page of memory where heap-allocated objects of a particular type are stored.
 ///
 /// A GcHeap is a collection of PageSets, and each PageSet is a collection of
@@ -371,14 +1019,34 @@ impl<U> TypedPage<U> {
     /// Allocate a `U`-sized-and-aligned region of uninitialized memory
     /// from this page.
     ///
+    /// If `verify_poison` is set (the page set has a quarantine budget
+    /// configured; see `PageSet::set_quarantine_budget`), and this slot was
+    /// last freed under a debug/test build, panics if anything has written
+    /// to it since — a use-after-free through a stale pointer.
+    ///
     /// # Safety
     ///
     /// This is safe unless GC is happening.
-    pub unsafe fn try_alloc(&mut self) -> Option<Pointer<U>> {
+    pub unsafe fn try_alloc(&mut self, verify_poison: bool) -> Option<Pointer<U>> {
         let p = self.header.freelist;
         if p.is_null() {
             None
         } else {
+            if verify_poison && (cfg!(debug_assertions) || cfg!(test)) {
+                // The first pointer-sized bytes hold the freelist link, not
+                // poison, so only the rest of the value is checked.
+                let link = mem::size_of::<*mut ()>();
+                let len = mem::size_of::<U>().saturating_sub(link);
+                let bytes = slice::from_raw_parts((p as *const u8).add(link), len);
+                if bytes.iter().any(|&b| b != SWEPT_BYTE) {
+                    panic!(
+                        "cell-gc: use after free detected: a quarantined allocation of type {:?} \
+                         was written to after being freed",
+                        self.header.type_id()
+                    );
+                }
+            }
+
             let listp = p as *mut *mut ();
             self.header.freelist = *listp;
             let ap = Pointer::new(p as *mut U);
@@ -390,27 +1058,78 @@ impl<U> TypedPage<U> {
         }
     }
 
-    unsafe fn sweep(&mut self) -> bool {
+    /// Sweep this page, calling `on_free` once for each object reclaimed.
+    /// `on_free` returns true if the slot should go straight back onto this
+    /// page's freelist, or false if it's being quarantined elsewhere (see
+    /// `PageSet::sweep`) and this page should *not* add it to the freelist.
+    /// A slot vacated by `compact` goes straight back onto the freelist
+    /// without a call to `on_free`.
+    ///
+    /// A user `Drop`/`Finalize` impl can panic. If one does, this keeps
+    /// sweeping the rest of the page rather than abandoning it half-swept --
+    /// see the "Panic safety" section of the module docs -- and re-raises
+    /// the first panic only once every slot has been accounted for.
+    unsafe fn sweep(&mut self, on_free: &mut dyn FnMut(UntypedPointer) -> bool) -> bool {
         let mut addr = self.begin();
         let end = self.end();
         let mut swept_any = false;
+        let mut panicked: Option<Box<dyn std::any::Any + Send + 'static>> = None;
         while addr < end {
             let mw = &mut *(addr as *mut MarkWord);
-            if mw.is_allocated() && !mw.is_marked() {
+            if mw.is_allocated() && mw.is_forwarded() {
+                // `compact` already copied this slot's value to the
+                // destination its forwarding pointer names and left this
+                // slot marked (since the object it used to hold is still
+                // live) -- it's the *slot*, not the value, that's retired
+                // here. Running `drop_in_place` on it would double-drop the
+                // value (now owned by the destination slot) and, worse, the
+                // value bytes here were already overwritten by the
+                // forwarding pointer itself.
                 let object_ptr = (addr + mem::size_of::<MarkWord>()) as *mut U;
-                ptr::drop_in_place(object_ptr);
+                mw.clear_allocated();
+                mw.clear_forwarded();
+
                 if cfg!(debug_assertions) || cfg!(test) {
-                    // Paint the unused memory with a known-bad value.
-                    const SWEPT_BYTE: u8 = 0xf4;
                     ptr::write_bytes(object_ptr, SWEPT_BYTE, 1);
                 }
-                mw.clear_allocated();
+                // The object lives on at its new address, so this is not a
+                // reclaimed object: the slot goes straight back onto the
+                // freelist without being reported to `on_free`.
                 self.add_to_free_list(object_ptr);
                 swept_any = true;
+            } else if mw.is_allocated() && !mw.is_marked() {
+                let object_ptr = (addr + mem::size_of::<MarkWord>()) as *mut U;
+
+                // Update the bookkeeping *before* running the destructor, so
+                // that if it panics this slot is already in a valid,
+                // re-collectible state rather than wedged as "allocated but
+                // its value has already been dropped".
+                mw.clear_allocated();
+
+                if let Err(payload) =
+                    panic::catch_unwind(AssertUnwindSafe(|| ptr::drop_in_place(object_ptr)))
+                {
+                    if panicked.is_none() {
+                        panicked = Some(payload);
+                    }
+                }
+
+                if cfg!(debug_assertions) || cfg!(test) {
+                    // Paint the unused memory with a known-bad value.
+                    ptr::write_bytes(object_ptr, SWEPT_BYTE, 1);
+                }
+                if on_free(UntypedPointer::new(object_ptr as *const ())) {
+                    self.add_to_free_list(object_ptr);
+                }
+                swept_any = true;
             }
             addr += Self::allocation_size();
         }
 
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+
         swept_any
     }
 }
@@ -421,8 +1140,30 @@ impl<U> TypedPage<U> {
 ///
 /// This must be called only after a full mark phase, to avoid sweeping objects
 /// that are still reachable.
-unsafe fn sweep_entry_point<'h, T: IntoHeapAllocation<'h>>(header: &mut PageHeader) -> bool {
-    header.downcast_mut::<T>().expect("page header corrupted").sweep()
+unsafe fn sweep_entry_point<'h, T: IntoHeapAllocation<'h>>(
+    header: &mut PageHeader,
+    on_free: &mut dyn FnMut(UntypedPointer) -> bool,
+) -> bool {
+    header.downcast_mut::<T>().expect("page header corrupted").sweep(on_free)
+}
+
+/// Promote every object still allocated and marked on a just-swept young
+/// page to the old generation.
+///
+/// # Safety
+///
+/// Must be called immediately after sweeping `page`, before its mark bits
+/// are cleared again.
+unsafe fn promote_survivors(page: &mut PageHeader) {
+    let mut addr = page.begin();
+    let end = page.end();
+    while addr < end {
+        let mw = &mut *(addr as *mut MarkWord);
+        if mw.is_allocated() && mw.is_marked() {
+            mw.set_old();
+        }
+        addr += page.allocation_size;
+    }
 }
 
 /// An unordered collection of memory pages that all share an allocation type.
@@ -431,7 +1172,19 @@ unsafe fn sweep_entry_point<'h, T: IntoHeapAllocation<'h>>(header: &mut PageHead
 pub struct PageSet {
     heap: *mut GcHeap,
 
-    sweep_fn: unsafe fn(&mut PageHeader) -> bool,
+    /// Where this set's pages come from. `HeapPageStore` (the default) pulls
+    /// them from the process heap; `MappedPageStore` backs them with a
+    /// memory-mapped file instead, for `GcHeapSession::save_to_file`/
+    /// `open_from_file`.
+    store: Box<dyn PageStore>,
+
+    sweep_fn: unsafe fn(&mut PageHeader, &mut dyn FnMut(UntypedPointer) -> bool) -> bool,
+
+    /// Runs one unmarked allocation's `Finalize::finalize` (see the
+    /// "Finalization" section of the module docs). Called once per
+    /// unmarked allocation by `finalize_unmarked`, between marking and
+    /// sweep.
+    finalize_fn: unsafe fn(UntypedPointer),
 
     /// Total number of pages in the following lists.
     page_count: usize,
@@ -444,6 +1197,66 @@ pub struct PageSet {
 
     /// The maximum number of pages, or None for no limit.
     limit: Option<usize>,
+
+    /// Number of completely empty pages to keep around (rather than
+    /// immediately handing their memory back to the OS) after a sweep, so
+    /// that a later `try_alloc` can reuse them without going back to the
+    /// allocator. Defaults to 0: empty pages are released right away.
+    retain_pages: usize,
+
+    /// If set, `sweep` holds freed slots in a bounded FIFO instead of
+    /// returning them to the freelist immediately, and `try_alloc` verifies
+    /// they haven't been written to since. Off by default; see
+    /// `set_quarantine_budget`.
+    quarantine: Option<Quarantine>,
+}
+
+/// A bounded FIFO of freed-but-not-yet-reusable allocations (see
+/// `PageSet::quarantine`). Slots leave in the order they entered, once the
+/// total size of everything still queued exceeds `budget`.
+struct Quarantine {
+    budget: usize,
+    bytes_queued: usize,
+    queue: VecDeque<(UntypedPointer, usize)>,
+}
+
+impl Quarantine {
+    fn new(budget: usize) -> Quarantine {
+        Quarantine { budget, bytes_queued: 0, queue: VecDeque::new() }
+    }
+
+    /// Add a freshly-freed slot to the quarantine, then drain slots from the
+    /// front (oldest first) back onto their pages' freelists until we're
+    /// back under budget.
+    fn enqueue(&mut self, ptr: UntypedPointer, value_size: usize) {
+        unsafe {
+            (*PageHeader::find(ptr)).quarantined_count += 1;
+        }
+        self.queue.push_back((ptr, value_size));
+        self.bytes_queued += value_size;
+
+        while self.bytes_queued > self.budget {
+            let (old_ptr, old_size) = match self.queue.pop_front() {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.bytes_queued -= old_size;
+            unsafe {
+                (*PageHeader::find(old_ptr)).add_to_free_list_raw(old_ptr);
+            }
+        }
+    }
+
+    /// Drain every queued slot back onto its page's freelist. Used when
+    /// quarantine is disabled after having been enabled.
+    fn drain(&mut self) {
+        while let Some((ptr, size)) = self.queue.pop_front() {
+            self.bytes_queued -= size;
+            unsafe {
+                (*PageHeader::find(ptr)).add_to_free_list_raw(ptr);
+            }
+        }
+    }
 }
 
 /// Apply a closure to every page in a linked list.
@@ -480,9 +1293,12 @@ impl Drop for PageSet {
                     let mut roots_to_ignore = vec![];
                     let next = (*page).next_page;
                     (*page).clear_mark_bits(&mut roots_to_ignore);
-                    (self.sweep_fn)(&mut *page); // drop all objects remaining in the page
+                    // Drop all objects remaining in the page; go straight to
+                    // each page's own freelist regardless of quarantine,
+                    // since we're about to free the page's memory anyway.
+                    (self.sweep_fn)(&mut *page, &mut |_| true);
                     ptr::drop_in_place(page); // drop the header
-                    Vec::from_raw_parts(page as *mut u8, 0, PAGE_SIZE); // free the page
+                    self.store.dealloc_page(page as *mut ()); // free the page
                     page = next;
                 }
             }
@@ -499,11 +1315,15 @@ impl PageSet {
     pub unsafe fn new<'h, T: IntoHeapAllocation<'h>>(heap: *mut GcHeap) -> PageSet {
         PageSet {
             heap,
+            store: Box::new(HeapPageStore),
             sweep_fn: sweep_entry_point::<T>,
+            finalize_fn: finalize_entry_point::<T>,
             page_count: 0,
             full_pages: ptr::null_mut(),
             other_pages: ptr::null_mut(),
             limit: None,
+            retain_pages: 0,
+            quarantine: None,
         }
     }
 
@@ -547,31 +1367,99 @@ impl PageSet {
         self.each_page_mut(|page| page.clear_mark_bits(roots));
     }
 
+    /// Run every currently-unmarked object's finalizer (see the
+    /// "Finalization" section of the module docs).
+    ///
+    /// # Safety
+    ///
+    /// Must be called only after a full mark phase and before `compact`/
+    /// `sweep` reclaim or relocate anything, so finalizers still see intact
+    /// field values.
+    pub(crate) unsafe fn finalize_unmarked(&self) {
+        let finalize_fn = self.finalize_fn;
+        self.each_page(|page| page.finalize_unmarked(finalize_fn));
+    }
+
     /// Sweep all unmarked objects from all pages.
     ///
+    /// Pages that end up completely empty are unlinked and their memory is
+    /// returned to the OS, except for up to `retain_pages` of them (see
+    /// `set_retain_pages`), which are kept around for a future `try_alloc` to
+    /// reuse without going back to the allocator.
+    ///
+    /// Returns the number of objects reclaimed, for `GcHeap::heap_stats`.
+    ///
     /// # Safety
     ///
     /// Safe to call only as the final part of GC.
-    pub unsafe fn sweep(&mut self) {
-        // Sweep nonfull pages.
-        each_page_mut(self.other_pages, |page| {
-            (self.sweep_fn)(page);
-        });
+    pub unsafe fn sweep(&mut self) -> usize {
+        let mut retained_empty = 0;
+        let mut reclaimed = 0;
+        let sweep_fn = self.sweep_fn;
+        let mut quarantine = self.quarantine.take();
+        let mut on_free = |ptr: UntypedPointer| -> bool {
+            reclaimed += 1;
+            match &mut quarantine {
+                None => true,
+                Some(q) => unsafe {
+                    let value_size = (*PageHeader::find(ptr)).allocation_size - mem::size_of::<MarkWord>();
+                    q.enqueue(ptr, value_size);
+                    false
+                }
+            }
+        };
+
+        // Sweep nonfull pages, freeing any that are completely empty (beyond
+        // the retained low-water mark).
+        let mut prev_page = &mut self.other_pages;
+        let mut page = *prev_page;
+        while !page.is_null() {
+            let next_page = (*page).next_page;
+            (sweep_fn)(&mut *page, &mut on_free);
+
+            if (*page).is_empty() {
+                if retained_empty < self.retain_pages {
+                    retained_empty += 1;
+                    prev_page = &mut (*page).next_page;
+                } else {
+                    *prev_page = next_page;
+                    ptr::drop_in_place(page);
+                    self.store.dealloc_page(page as *mut ());
+                    self.page_count -= 1;
+                }
+            } else {
+                prev_page = &mut (*page).next_page;
+            }
+            page = next_page;
+        }
 
         // Sweep full pages. Much more complicated because we have to move
-        // pages from one list to the other if any space is freed.
+        // pages from one list to the other if any space is freed, or free
+        // the page outright if it ends up completely empty. A page whose
+        // freed slots all went into quarantine has nothing on its freelist
+        // yet, so it stays on the full list.
         let mut prev_page = &mut self.full_pages;
         let mut page = *prev_page;
         while !page.is_null() {
-            if (self.sweep_fn)(&mut *page) {
+            if (sweep_fn)(&mut *page, &mut on_free) && !(*page).freelist.is_null() {
                 let next_page = (*page).next_page;
 
                 // remove from full list
                 *prev_page = next_page;
 
-                // add to nonfull list
-                (*page).next_page = self.other_pages;
-                self.other_pages = page;
+                if (*page).is_empty() && retained_empty >= self.retain_pages {
+                    ptr::drop_in_place(page);
+                    self.store.dealloc_page(page as *mut ());
+                    self.page_count -= 1;
+                } else {
+                    if (*page).is_empty() {
+                        retained_empty += 1;
+                    }
+
+                    // add to nonfull list
+                    (*page).next_page = self.other_pages;
+                    self.other_pages = page;
+                }
 
                 page = next_page;
             } else {
@@ -579,6 +1467,103 @@ impl PageSet {
                 page = *prev_page;
             }
         }
+
+        drop(on_free);
+        self.quarantine = quarantine;
+        reclaimed
+    }
+
+    /// Evacuate live (marked), unpinned objects out of this set's full pages
+    /// and into its densest non-full pages, leaving a forwarding pointer (see
+    /// `MarkWord::set_forwarded`) behind in each relocated object's old
+    /// slot.
+    ///
+    /// Only pages with no free slot at the start of the pass are evacuated:
+    /// every page with a free slot is a candidate target and is never used
+    /// as a source, so a sparse page that still has free slots is not
+    /// emptied by this pass. Pages are ranked by allocated slots, which
+    /// before `sweep` still include unmarked garbage.
+    ///
+    /// Pages this manages to empty out entirely are left in place for
+    /// `sweep` to notice and free; `compact` itself never unlinks a page.
+    ///
+    /// # Safety
+    ///
+    /// Must be called after a full mark phase (so mark bits reflect
+    /// reachability) and before mark bits are cleared or pages are swept.
+    /// The caller must re-trace every live edge afterward and rewrite any
+    /// that target a forwarded object, before sweeping; otherwise those
+    /// edges are left dangling into a page about to be freed.
+    pub unsafe fn compact(&mut self) {
+        let mut pages: Vec<*mut PageHeader> = Vec::new();
+        self.each_page_mut(|page| pages.push(page as *mut PageHeader));
+        pages.sort_by_key(|&p| (*p).live_count());
+
+        // Candidate targets: the densest pages with at least one free slot.
+        // `pages` is sorted sparsest-first, so preserving that order here
+        // means the *end* of `targets` -- what `.last()`/`.pop()` below
+        // consume -- is the densest candidate, and we fill up nearly-full
+        // pages first.
+        let mut targets: Vec<*mut PageHeader> = pages
+            .iter()
+            .cloned()
+            .filter(|&p| !(*p).freelist.is_null())
+            .collect();
+
+        // Fixed up front and never mutated: which pages were target
+        // candidates at all, so a page that `targets.pop()` later drops
+        // (because it filled up) doesn't turn into a source that
+        // re-evacuates the objects just copied into it. `targets` itself
+        // can't be used for this check since it's drained as we go.
+        let target_set: HashSet<*mut PageHeader> = targets.iter().cloned().collect();
+
+        // Never evacuate into a page we might also evacuate out of later in
+        // this same pass; that would just shuffle objects sideways.
+        'sources: for &source in pages.iter() {
+            if target_set.contains(&source) {
+                continue;
+            }
+
+            // A page with too many pinned allocations isn't worth touching:
+            // we'd do a lot of copying for little chance of freeing it.
+            let live = (*source).live_count();
+            if live == 0 {
+                continue;
+            }
+            let pinned = (*source).pinned_count();
+            if pinned * COMPACTION_PIN_THRESHOLD_DEN > live * COMPACTION_PIN_THRESHOLD_NUM {
+                continue;
+            }
+
+            let allocation_size = (*source).allocation_size;
+            let value_size = allocation_size - mem::size_of::<MarkWord>();
+            let mut addr = (*source).begin();
+            let end = (*source).end();
+            while addr < end {
+                let mw = &mut *(addr as *mut MarkWord);
+                if mw.is_allocated() && mw.is_marked() && !mw.is_pinned() {
+                    while targets.last().map_or(false, |&t| (*t).freelist.is_null()) {
+                        targets.pop();
+                    }
+                    let new_slot = match targets.last() {
+                        Some(&t) if t != source => match (*t).try_alloc_raw() {
+                            Some(slot) => slot,
+                            None => break 'sources,
+                        },
+                        _ => break 'sources, // no room left anywhere
+                    };
+
+                    let value_ptr = (addr + mem::size_of::<MarkWord>()) as *mut u8;
+                    ptr::copy_nonoverlapping(value_ptr, new_slot as *mut u8, value_size);
+
+                    // Leave a forwarding pointer behind so the caller's
+                    // fix-up pass can redirect edges that still point here.
+                    ptr::write(value_ptr as *mut usize, new_slot as usize);
+                    mw.set_forwarded();
+                }
+                addr += allocation_size;
+            }
+        }
     }
 
     /// True if nothing is allocated in this set of pages.
@@ -588,9 +1573,233 @@ impl PageSet {
         empty
     }
 
+    /// Collect extra mark roots for a minor collection: every allocated
+    /// slot in a dirty card of an old-generation page.
+    pub(crate) fn collect_dirty_roots(&self, roots: &mut Vec<UntypedPointer>) {
+        self.each_page(|page| page.dirty_card_objects(roots));
+    }
+
+    /// Sweep only young-generation pages, leaving old pages completely
+    /// untouched, and promote every object that survives to old.
+    ///
+    /// Returns the number of objects reclaimed, for `GcHeap::heap_stats`.
+    ///
+    /// # Safety
+    ///
+    /// Must be called only after a mark phase that seeded its roots with
+    /// (at least) the real root set plus `collect_dirty_roots`.
+    pub unsafe fn minor_sweep(&mut self) -> usize {
+        let mut retained_empty = 0;
+        let mut reclaimed = 0;
+        let sweep_fn = self.sweep_fn;
+        let mut quarantine = self.quarantine.take();
+        let mut on_free = |ptr: UntypedPointer| -> bool {
+            reclaimed += 1;
+            match &mut quarantine {
+                None => true,
+                Some(q) => unsafe {
+                    let value_size = (*PageHeader::find(ptr)).allocation_size - mem::size_of::<MarkWord>();
+                    q.enqueue(ptr, value_size);
+                    false
+                }
+            }
+        };
+
+        let mut prev_page = &mut self.other_pages;
+        let mut page = *prev_page;
+        while !page.is_null() {
+            let next_page = (*page).next_page;
+            if !(*page).young {
+                prev_page = &mut (*page).next_page;
+                page = next_page;
+                continue;
+            }
+
+            (sweep_fn)(&mut *page, &mut on_free);
+            promote_survivors(&mut *page);
+
+            if (*page).is_empty() {
+                if retained_empty < self.retain_pages {
+                    retained_empty += 1;
+                    prev_page = &mut (*page).next_page;
+                } else {
+                    *prev_page = next_page;
+                    ptr::drop_in_place(page);
+                    self.store.dealloc_page(page as *mut ());
+                    self.page_count -= 1;
+                }
+            } else {
+                prev_page = &mut (*page).next_page;
+            }
+            page = next_page;
+        }
+
+        let mut prev_page = &mut self.full_pages;
+        let mut page = *prev_page;
+        while !page.is_null() {
+            if !(*page).young {
+                prev_page = &mut (*page).next_page;
+                page = *prev_page;
+                continue;
+            }
+
+            if (sweep_fn)(&mut *page, &mut on_free) && !(*page).freelist.is_null() {
+                promote_survivors(&mut *page);
+                let next_page = (*page).next_page;
+                *prev_page = next_page;
+
+                if (*page).is_empty() && retained_empty >= self.retain_pages {
+                    ptr::drop_in_place(page);
+                    self.store.dealloc_page(page as *mut ());
+                    self.page_count -= 1;
+                } else {
+                    if (*page).is_empty() {
+                        retained_empty += 1;
+                    }
+                    (*page).next_page = self.other_pages;
+                    self.other_pages = page;
+                }
+
+                page = next_page;
+            } else {
+                promote_survivors(&mut *page);
+                prev_page = &mut (*page).next_page;
+                page = *prev_page;
+            }
+        }
+
+        drop(on_free);
+        self.quarantine = quarantine;
+        reclaimed
+    }
+
+    /// Called after a major collection: every page that's still around just
+    /// had its contents verified live from the real roots, so promote it to
+    /// old wholesale and clear its dirty cards (any old-to-young edge it
+    /// had is now either old-to-old, since minor generations don't survive
+    /// a major collection in place, or gone).
+    pub(crate) fn promote_all_and_clear_cards(&mut self) {
+        self.each_page_mut(|page| {
+            page.young = false;
+            page.dirty_cards = 0;
+        });
+    }
+
     pub fn set_page_limit(&mut self, limit: Option<usize>) {
         self.limit = limit;
     }
+
+    /// Set the number of completely empty pages that `sweep` should cache
+    /// for fast re-allocation instead of releasing back to the OS.
+    pub fn set_retain_pages(&mut self, n: usize) {
+        self.retain_pages = n;
+    }
+
+    /// Enable or disable the freed-object quarantine.
+    ///
+    /// With `Some(bytes)`, `sweep` holds up to `bytes` worth of freed slots
+    /// in a FIFO instead of returning them to the freelist right away, and
+    /// `try_alloc` panics if it ever hands back a quarantined slot whose
+    /// poison bytes (under debug/test builds) have been disturbed — catching
+    /// a write through a dangling pointer instead of silently reusing the
+    /// memory. `None` (the default) disables quarantine and immediately
+    /// frees anything still queued.
+    pub fn set_quarantine_budget(&mut self, budget: Option<usize>) {
+        match budget {
+            Some(bytes) => {
+                self.quarantine
+                    .get_or_insert_with(|| Quarantine::new(bytes))
+                    .budget = bytes;
+            }
+            None => {
+                if let Some(mut q) = self.quarantine.take() {
+                    q.drain();
+                }
+            }
+        }
+    }
+
+    /// Report page count, live/free slot counts, and per-value size for this
+    /// set. See `GcHeap::heap_stats` for the heap-wide aggregate.
+    pub(crate) fn stats(&self) -> TypeStats {
+        let mut stats = TypeStats::default();
+        self.each_page(|page| {
+            stats.page_count += 1;
+            stats.live_count += page.live_count();
+            stats.free_count += page.slot_count() - page.live_count();
+            stats.bytes_per_allocation = page.allocation_size - mem::size_of::<MarkWord>();
+        });
+        stats.pages_until_limit = self.limit.map(|limit| limit.saturating_sub(stats.page_count));
+        stats
+    }
+}
+
+#[cfg(feature = "persistent")]
+impl PageSet {
+    /// Build a `PageSet` whose pages live in a memory-mapped file, picking
+    /// up wherever `file` left off (or starting empty, if it's freshly
+    /// created). See `GcHeapSession::open_from_file`.
+    pub(crate) unsafe fn open<'h, T: IntoHeapAllocation<'h>>(
+        heap: *mut GcHeap,
+        file: &File,
+        min_pages: usize,
+    ) -> io::Result<PageSet> {
+        let store = MappedPageStore::new(file, min_pages)?;
+        let (full_pages, other_pages, delta) = store.saved_pages();
+
+        let mut page_set = PageSet {
+            heap,
+            store: Box::new(store),
+            sweep_fn: sweep_entry_point::<T>,
+            finalize_fn: finalize_entry_point::<T>,
+            page_count: 0,
+            full_pages,
+            other_pages,
+            limit: None,
+            retain_pages: 0,
+            quarantine: None,
+        };
+        page_set.relocate::<T>(delta);
+        Ok(page_set)
+    }
+
+    /// Reset every page's `mark_fn`/`rebase_fn`/`allocation_size` to match
+    /// `T` in this process -- the ones saved in the file are code pointers
+    /// from whatever process wrote it last and can't be trusted -- then
+    /// shift every pointer on every page by `delta` to account for this
+    /// mapping landing at a different address than it did last time.
+    fn relocate<'h, T: IntoHeapAllocation<'h>>(&mut self, delta: isize) {
+        let mut page_count = 0;
+        for &head in &[self.full_pages, self.other_pages] {
+            each_page_mut(head, |page| {
+                page.mark_fn = mark_entry_point::<T>;
+                page.rebase_fn = rebase_entry_point::<T>;
+                page.allocation_size = TypedPage::<T::In>::allocation_size();
+                page_count += 1;
+            });
+        }
+        self.page_count = page_count;
+
+        let heap = self.heap;
+        for &head in &[self.full_pages, self.other_pages] {
+            each_page_mut(head, |page| unsafe { page.rebase(heap, delta) });
+        }
+    }
+
+    /// Record this set's page list in the file backing it and flush to disk.
+    ///
+    /// # Panics
+    ///
+    /// If this `PageSet` wasn't created with `PageSet::open` -- i.e. its
+    /// pages live in the process heap, not a file.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        let full_pages = self.full_pages;
+        let other_pages = self.other_pages;
+        self.store
+            .as_mapped_mut()
+            .expect("flush called on a PageSet whose pages aren't file-backed")
+            .flush(full_pages, other_pages)
+    }
 }
 
 pub struct PageSetRef<'a, 'h, T: IntoHeapAllocation<'h> + 'a> {
@@ -622,7 +1831,7 @@ impl<'a, 'h, T: IntoHeapAllocation<'h> + 'a> PageSetRef<'a, 'h, T> {
             // We have a nonfull page. Allocation can't fail.
             assert!(!(*front_page).freelist.is_null());
             let page = (*front_page).downcast_mut::<T>().unwrap();
-            let ptr = page.try_alloc().unwrap();
+            let ptr = page.try_alloc(self.quarantine.is_some()).unwrap();
 
             // If the page is full now, move it to the other list.
             if page.freelist.is_null() {
@@ -637,9 +1846,10 @@ impl<'a, 'h, T: IntoHeapAllocation<'h> + 'a> PageSetRef<'a, 'h, T> {
         }
 
         // If there is a limit and we already have at least that many pages, fail.
+        let verify_poison = self.quarantine.is_some();
         match self.limit {
             Some(limit) if self.page_count >= limit => None,
-            _ => self.new_page().try_alloc(),
+            _ => self.new_page().try_alloc(verify_poison),
         }
     }
 
@@ -669,8 +1879,12 @@ impl<'a, 'h, T: IntoHeapAllocation<'h> + 'a> PageSetRef<'a, 'h, T> {
                     "Types with exotic alignment requirements are not supported");
         }
 
-        let mut vec: Vec<u8> = Vec::with_capacity(PAGE_SIZE);
-        let raw_page = vec.as_mut_ptr() as *mut ();
+        let raw_page = unsafe { self.page_set.store.alloc_page() };
+        if cfg!(debug_assertions) || cfg!(test) {
+            // Poison every slot up front, so the quarantine's check in
+            // `TypedPage::try_alloc` passes on never-used slots too.
+            unsafe { ptr::write_bytes(raw_page as *mut u8, SWEPT_BYTE, PAGE_SIZE) };
+        }
 
         // Rust makes no guarantee whatsoever that this will work.
         // If it doesn't, panic.
@@ -695,8 +1909,13 @@ impl<'a, 'h, T: IntoHeapAllocation<'h> + 'a> PageSetRef<'a, 'h, T> {
                         heap: self.page_set.heap,
                         next_page: *list_head,
                         mark_fn: mark_entry_point::<T>,
+                        #[cfg(feature = "persistent")]
+                        rebase_fn: rebase_entry_point::<T>,
                         freelist: ptr::null_mut(),
-                        allocation_size: TypedPage::<T::In>::allocation_size()
+                        allocation_size: TypedPage::<T::In>::allocation_size(),
+                        young: true,
+                        dirty_cards: 0,
+                        quarantined_count: 0,
                     },
                     allocations: PhantomData,
                 },
@@ -705,9 +1924,7 @@ impl<'a, 'h, T: IntoHeapAllocation<'h> + 'a> PageSetRef<'a, 'h, T> {
             let page = &mut *page_ptr;
             page.init_mark_words_and_freelist();
 
-            // Remove the memory from the vector and link it into
-            // the PageSet's linked list.
-            mem::forget(vec);
+            // Link the new page into the PageSet's linked list.
             *list_head = &mut page.header;
             self.page_set.page_count += 1;
 
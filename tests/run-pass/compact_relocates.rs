@@ -0,0 +1,40 @@
+//! Check that a compacting collection's relocated objects actually survive
+//! it, and that a `Root<T>` handle still reads back the original value
+//! after its target is moved.
+//!
+//! Regression test: `PageHeader::try_alloc_raw` (used by `PageSet::compact`
+//! to hand out a relocated object's destination slot) set `ALLOCATED_BIT`
+//! on that slot but never set `MARK_BIT`, so the `sweep` later in the same
+//! `gc_cycle` reclaimed every object compaction had just moved. Separately,
+//! `Root<T>` didn't have its stored pointer rewritten when its target was
+//! relocated, so it kept pointing at memory `sweep` was about to reclaim.
+
+extern crate cell_gc;
+#[macro_use] extern crate cell_gc_derive;
+mod pairs_aux;
+use cell_gc::*;
+use pairs_aux::*;
+
+fn main() {
+    with_heap(|hs| {
+        hs.set_compacting_gc(true);
+
+        // Keep one object alive, then fill the rest of its page with garbage
+        // until a second page is started. `compact` only evacuates pages
+        // with no free slot, so the next collection moves the survivor off
+        // its full page and into the second, non-full one.
+        let survivor = alloc_pair(hs, Value::Int(999), Value::Null);
+        let root = hs.root(survivor);
+
+        let mut i = 0;
+        while hs.heap_stats().get::<Pair>().unwrap().page_count < 2 {
+            alloc_pair(hs, Value::Int(i), Value::Null);
+            i += 1;
+        }
+
+        hs.force_gc();
+
+        let survivor = hs.get_root(&root);
+        assert_eq!(survivor.head(), Value::Int(999));
+    });
+}
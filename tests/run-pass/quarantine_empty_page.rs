@@ -0,0 +1,36 @@
+//! Check that a page whose entire contents get quarantined in the same
+//! sweep doesn't get its backing memory released out from under the
+//! quarantine.
+//!
+//! Regression test: `PageHeader::is_empty` only checked `ALLOCATED_BIT`,
+//! which `TypedPage::sweep` clears on quarantined slots too, even though their pointers keep living in `Quarantine`'s
+//! queue rather than the page's freelist. If every live object on a page
+//! was quarantined in the same sweep, `is_empty` reported the page empty
+//! and the retention logic freed it while `Quarantine` still held pointers
+//! into it -- a use-after-free the next time one of them left quarantine.
+
+extern crate cell_gc;
+#[macro_use] extern crate cell_gc_derive;
+mod pairs_aux;
+use cell_gc::*;
+use pairs_aux::*;
+
+fn main() {
+    with_heap(|hs| {
+        hs.set_quarantine_budget::<Pair>(Some(1 << 20));
+
+        // Nothing here is rooted, so the next collection frees this whole
+        // page straight into quarantine rather than onto its freelist.
+        for i in 0..32 {
+            alloc_pair(hs, Value::Int(i), Value::Null);
+        }
+        hs.force_gc();
+
+        // If `is_empty` had let the page's memory be released anyway, this
+        // allocation (and the use-after-free check `TypedPage::try_alloc`
+        // runs in debug/test builds) would land on memory quarantine still
+        // thinks is poisoned, previously-live data.
+        alloc_pair(hs, Value::Int(1), Value::Null);
+        hs.force_gc();
+    });
+}